@@ -0,0 +1,46 @@
+use rackbox_sysfs::Source;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+
+pub const DEFAULT_PATH: &str = "/etc/rackbox-fancontroller/config.toml";
+
+/// Configuração opcional; sem o arquivo o daemon roda com os valores padrão.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// `host:port` do endpoint Prometheus `/metrics`; desligado se ausente.
+    pub metrics_listen: Option<String>,
+    /// Temperatura seguida pelo fan, ex. `thermal:cpu-thermal` ou
+    /// `hwmon:nvme/Composite`; `thermal:thermal_zone0` se ausente.
+    #[serde(default, deserialize_with = "sensor")]
+    pub sensor: Source,
+}
+
+/// Lê `sensor` com a mensagem de erro em português.
+fn sensor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Source, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    Source::try_from(text.clone()).map_err(|_| serde::de::Error::custom(format!(
+        "sensor '{}' inválido, use \"hwmon:<chip>[/<label>]\" ou \"thermal:<tipo>\"", text)))
+}
+
+impl Config {
+    /// Carrega `path`; a falta do arquivo só é erro quando ele foi pedido
+    /// explicitamente (--config).
+    pub fn load(path: &str, explicit: bool) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        };
+        let config: Config = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(addr) = &config.metrics_listen
+            && addr.parse::<SocketAddr>().is_err()
+        {
+            return Err(format!("{}: metrics_listen '{}' não é um endereço como 0.0.0.0:9102",
+                               path, addr));
+        }
+        Ok(config)
+    }
+}
@@ -1,11 +1,20 @@
+mod config;
+mod metrics;
+mod sysfs;
+
 use rppal::gpio::Gpio;
-use std::fs;
+use std::path::Path;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use syslog::{Facility, Formatter3164};
 use nix::unistd::{fork, ForkResult};
 use std::process;
 use std::os::unix::io::AsRawFd; // Importação do trait AsRawFd
+use std::sync::atomic::{AtomicBool, Ordering};
+use nix::sys::signal::{self, SigHandler, Signal};
+use config::Config;
+use metrics::{MetricsServer, Stats};
+use rackbox_notify::Notifier;
 
 // Constantes de configuração
 const GPIO_FAN_PIN: u8 = 17; // Pino GPIO para controlar o fan
@@ -13,12 +22,11 @@ const TEMP_MIN: f32 = 40.0;  // Temperatura mínima para desligar o fan
 const TEMP_MAX: f32 = 50.0;  // Temperatura máxima para ligar o fan
 const POLL_INTERVAL: u64 = 10; // Intervalo de verificação da temperatura em segundos
 
-fn get_cpu_temperature() -> f32 {
-    // Lê a temperatura da CPU do arquivo do sistema
-    let temp_str = fs::read_to_string("/sys/class/thermal/thermal_zone0/temp")
-        .expect("Falha ao ler a temperatura da CPU");
-    let temp_millicelsius: f32 = temp_str.trim().parse().expect("Temperatura inválida");
-    temp_millicelsius / 1000.0
+// Sinalizado por SIGTERM/SIGINT para encerrar o loop
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_stop(_: nix::libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
 }
 
 fn log_to_syslog(message: &str) {
@@ -38,6 +46,25 @@ fn log_to_syslog(message: &str) {
     }
 }
 
+// Leva ao syslog os avisos que o rackbox_notify registra pelo crate `log`
+struct SyslogBridge;
+
+impl log::Log for SyslogBridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            log_to_syslog(&record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static SYSLOG_BRIDGE: SyslogBridge = SyslogBridge;
+
 fn daemonize() {
     // Primeiro fork
     match unsafe { fork() } {
@@ -80,28 +107,128 @@ fn daemonize() {
 }
 
 fn main() {
+    // --foreground: não faz fork, para o systemd supervisionar (Type=notify)
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let foreground = args.iter().any(|arg| arg == "--foreground" || arg == "-f");
+
+    // --list-sensors: mostra as temperaturas hwmon/thermal zone disponíveis para "sensor"
+    if args.iter().any(|arg| arg == "--list-sensors") {
+        sysfs::list(Path::new(rackbox_sysfs::SYS_CLASS));
+        return;
+    }
+
+    // --config <arquivo>: configuração opcional (padrão /etc/rackbox-fancontroller/config.toml)
+    let config_arg = args.iter().position(|arg| arg == "--config").map(|i| args.get(i + 1));
+    let config = match config_arg {
+        Some(Some(path)) => Config::load(path, true),
+        Some(None) => Err("--config requer um arquivo".to_string()),
+        None => Config::load(config::DEFAULT_PATH, false),
+    };
+    let config = config.unwrap_or_else(|e| {
+        eprintln!("Configuração inválida: {}", e);
+        process::exit(1);
+    });
+
     // Transforma o processo em um daemon
-    daemonize();
+    if !foreground {
+        daemonize();
+    }
+
+    // Encerra o loop de forma limpa ao receber SIGTERM (systemctl stop) ou SIGINT
+    unsafe {
+        signal::signal(Signal::SIGTERM, SigHandler::Handler(handle_stop))
+            .expect("Falha ao instalar o handler de SIGTERM");
+        signal::signal(Signal::SIGINT, SigHandler::Handler(handle_stop))
+            .expect("Falha ao instalar o handler de SIGINT");
+    }
 
     // Inicializa o GPIO após a daemonização
     let gpio = Gpio::new().expect("Falha ao inicializar o GPIO");
     let mut fan_pin = gpio.get(GPIO_FAN_PIN).unwrap().into_output();
 
-    log_to_syslog("Serviço de controle do fan iniciado.");
+    log_to_syslog(&format!("Serviço de controle do fan iniciado. Sensor: {}", config.sensor));
+
+    // Servidor de métricas opcional; uma falha não impede o controle do fan
+    let metrics = config.metrics_listen.as_deref().and_then(|addr| {
+        match MetricsServer::bind(addr) {
+            Ok(server) => {
+                log_to_syslog(&format!("Métricas Prometheus em http://{}/metrics", addr));
+                Some(server)
+            }
+            Err(e) => {
+                log_to_syslog(&format!("Métricas desativadas, falha ao escutar em {}: {}", addr, e));
+                None
+            }
+        }
+    });
+    let mut stats = Stats { sensor: config.sensor.to_string(), ..Stats::default() };
+    let mut failsafe = false;
+
+    if log::set_logger(&SYSLOG_BRIDGE).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    let notifier = Notifier::from_env();
+    notifier.ready();
+
+    while !STOP_REQUESTED.load(Ordering::SeqCst) {
+        let started = Instant::now();
+        let was_on = fan_pin.is_set_high();
 
-    loop {
-        let temp = get_cpu_temperature();
-        println!("Temperatura da CPU: {:.1}°C", temp);
+        match sysfs::read_temperature(Path::new(rackbox_sysfs::SYS_CLASS), &config.sensor) {
+            Ok(temp) => {
+                println!("Temperatura {}: {:.1}°C", config.sensor, temp);
+                stats.temperature = Some(temp);
+                if failsafe {
+                    failsafe = false;
+                    log_to_syslog(&format!("Leitura recuperada: {:.1}°C", temp));
+                }
 
-        if temp >= TEMP_MAX && !fan_pin.is_set_high() {
-            fan_pin.set_high(); // Liga o fan
-            log_to_syslog(&format!("Fan ligado. Temperatura: {:.1}°C", temp));
-        } else if temp <= TEMP_MIN && fan_pin.is_set_high() {
-            fan_pin.set_low(); // Desliga o fan
-            log_to_syslog(&format!("Fan desligado. Temperatura: {:.1}°C", temp));
+                if temp >= TEMP_MAX && !fan_pin.is_set_high() {
+                    fan_pin.set_high(); // Liga o fan
+                    log_to_syslog(&format!("Fan ligado. Temperatura: {:.1}°C", temp));
+                } else if temp <= TEMP_MIN && fan_pin.is_set_high() {
+                    fan_pin.set_low(); // Desliga o fan
+                    log_to_syslog(&format!("Fan desligado. Temperatura: {:.1}°C", temp));
+                }
+            }
+            Err(e) => {
+                // Fail-safe: sem leitura, liga o fan
+                stats.read_errors += 1;
+                stats.temperature = None;
+                if !failsafe {
+                    failsafe = true;
+                    stats.failsafe_activations += 1;
+                    log_to_syslog(&format!("{}. Fail-safe: fan ligado", e));
+                }
+                fan_pin.set_high();
+            }
         }
 
-        thread::sleep(Duration::from_secs(POLL_INTERVAL));
+        let estado = if fan_pin.is_set_high() { "ligado" } else { "desligado" };
+        match stats.temperature {
+            Some(temp) => notifier.status(&format!("{} {:.1}°C, fan {}", config.sensor, temp, estado)),
+            None => notifier.status(&format!("sem leitura de {}, fan {} (fail-safe)", config.sensor, estado)),
+        }
+        notifier.watchdog();
+
+        stats.fan_on = fan_pin.is_set_high();
+        if stats.fan_on != was_on {
+            stats.switches += 1;
+        }
+        stats.loop_duration = started.elapsed();
+        if let Some(metrics) = &metrics {
+            metrics.publish(&stats);
+        }
+
+        // Dorme em passos de 1s para responder rápido ao SIGTERM
+        for _ in 0..POLL_INTERVAL {
+            if STOP_REQUESTED.load(Ordering::SeqCst) {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
     }
-}
 
+    notifier.stopping();
+    log_to_syslog("Serviço de controle do fan encerrado.");
+}
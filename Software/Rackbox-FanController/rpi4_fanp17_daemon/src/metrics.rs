@@ -0,0 +1,145 @@
+//! Endpoint Prometheus `/metrics`. O loop principal gera a página a cada
+//! ciclo; uma thread em segundo plano serve a última cópia por HTTP simples.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Contadores e últimos valores do loop de controle.
+#[derive(Default)]
+pub struct Stats {
+    /// Sensor configurado, ex. `thermal:cpu-thermal`.
+    pub sensor: String,
+    pub temperature: Option<f32>,
+    pub fan_on: bool,
+    pub switches: u64,
+    pub read_errors: u64,
+    pub failsafe_activations: u64,
+    pub loop_duration: Duration,
+}
+
+pub struct MetricsServer {
+    page: Arc<Mutex<String>>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let page = Arc::new(Mutex::new(String::new()));
+
+        let served = Arc::clone(&page);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve(stream, &served);
+            }
+        });
+        Ok(MetricsServer { page })
+    }
+
+    pub fn publish(&self, stats: &Stats) {
+        if let Ok(mut page) = self.page.lock() {
+            *page = render(stats);
+        }
+    }
+}
+
+fn serve(stream: TcpStream, page: &Mutex<String>) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(&stream);
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Ignora os cabeçalhos
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        header.clear();
+    }
+
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", page.lock().map(|p| p.clone()).unwrap_or_default()),
+        _ => ("404 Not Found", "try /metrics\n".to_string()),
+    };
+    let mut writer = &stream;
+    let _ = write!(writer,
+                   "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body);
+}
+
+/// Escreve uma amostra com o cabeçalho `# HELP` / `# TYPE`; `labels` é vazio
+/// ou um conjunto `{nome="valor"}`.
+fn metric(out: &mut String, name: &str, labels: &str, kind: &str, help: &str,
+          value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{}{} {}", name, labels, value);
+}
+
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+    let sensor = format!("{{sensor=\"{}\"}}", label(&stats.sensor));
+    if let Some(temp) = stats.temperature {
+        metric(&mut out, "rackbox_fancontroller_temperature_celsius", &sensor, "gauge",
+               "Last temperature read from the configured sensor.", temp);
+    }
+    metric(&mut out, "rackbox_fancontroller_fan_on", "", "gauge",
+           "1 while the fan is running.", u8::from(stats.fan_on));
+    metric(&mut out, "rackbox_fancontroller_fan_duty_percent", "", "gauge",
+           "Fan duty cycle (on/off only: 0 or 100).", if stats.fan_on { 100 } else { 0 });
+    metric(&mut out, "rackbox_fancontroller_fan_switches_total", "", "counter",
+           "Times the fan was switched on or off.", stats.switches);
+    metric(&mut out, "rackbox_fancontroller_sensor_read_errors_total", &sensor, "counter",
+           "Failed temperature reads.", stats.read_errors);
+    metric(&mut out, "rackbox_fancontroller_failsafe_activations_total", "", "counter",
+           "Times the fan was forced on after a failed read.", stats.failsafe_activations);
+    metric(&mut out, "rackbox_fancontroller_loop_duration_seconds", "", "gauge",
+           "Time the last control cycle took.", stats.loop_duration.as_secs_f64());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_labels_the_sensor_and_counts_switches_errors_and_failsafe() {
+        let stats = Stats {
+            sensor: "hwmon:nvme/Composite".to_string(),
+            temperature: Some(41.5),
+            fan_on: true,
+            switches: 3,
+            read_errors: 2,
+            failsafe_activations: 1,
+            loop_duration: Duration::from_millis(250),
+        };
+        let page = render(&stats);
+        for line in [
+            "# TYPE rackbox_fancontroller_temperature_celsius gauge",
+            "rackbox_fancontroller_temperature_celsius{sensor=\"hwmon:nvme/Composite\"} 41.5",
+            "rackbox_fancontroller_fan_on 1",
+            "rackbox_fancontroller_fan_duty_percent 100",
+            "rackbox_fancontroller_fan_switches_total 3",
+            "rackbox_fancontroller_sensor_read_errors_total{sensor=\"hwmon:nvme/Composite\"} 2",
+            "rackbox_fancontroller_failsafe_activations_total 1",
+            "rackbox_fancontroller_loop_duration_seconds 0.25",
+        ] {
+            assert!(page.lines().any(|l| l == line), "missing {:?} in:\n{}", line, page);
+        }
+    }
+
+    #[test]
+    fn temperature_is_left_out_until_a_read_succeeds() {
+        let stats = Stats { sensor: "thermal:cpu-thermal".to_string(), ..Stats::default() };
+        let page = render(&stats);
+        assert!(!page.contains("temperature_celsius"), "{}", page);
+        assert!(page.contains("rackbox_fancontroller_fan_on 0"));
+    }
+}
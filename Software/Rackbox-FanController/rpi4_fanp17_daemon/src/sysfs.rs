@@ -0,0 +1,35 @@
+//! Temperaturas expostas pelo kernel em /sys/class (chips hwmon e thermal
+//! zones), encontradas pelo crate rackbox_sysfs.
+
+use rackbox_sysfs::{ReadError, Source, find, inputs};
+use std::path::Path;
+
+/// Lê um arquivo de temperatura em milésimos de grau.
+fn read_millidegrees(path: &Path) -> Result<f32, String> {
+    rackbox_sysfs::read_millidegrees(path).map_err(|e| match e {
+        ReadError::Io(e) => format!("Falha ao ler {}: {}", path.display(), e),
+        ReadError::Invalid(text) => format!("Temperatura inválida: {:?}", text),
+    })
+}
+
+/// Lê `source` em `root`. A busca é refeita a cada leitura, já que a
+/// numeração hwmon pode mudar entre boots e o driver de um chip pode carregar
+/// tarde.
+pub fn read_temperature(root: &Path, source: &Source) -> Result<f32, String> {
+    let path = find(root, source)
+        .ok_or_else(|| format!("Sensor {} não encontrado em {}", source, root.display()))?;
+    read_millidegrees(&path)
+}
+
+/// `--list-sensors`: cada entrada com a temperatura atual.
+pub fn list(root: &Path) {
+    let inputs = inputs(root);
+    if inputs.is_empty() {
+        println!("Nenhuma temperatura hwmon ou thermal zone em {}", root.display());
+    }
+    for input in inputs {
+        let temp = read_millidegrees(&input.path)
+            .map_or_else(|e| format!("erro: {}", e), |t| format!("{:>6.1}°C", t));
+        println!("{:<36} {:<14} {}", input.source.to_string(), input.alias, temp);
+    }
+}
@@ -1,3 +1,4 @@
+
 use rppal::gpio::Gpio;
 use std::thread;
 use std::time::Duration;
@@ -6,8 +7,8 @@ use std::sync::Arc;
 use std::process;
 use syslog::{Facility, Formatter3164, BasicLogger};
 use log::{error, info}; // Removida a importação não utilizada `warn`
-use libc;
 use std::os::fd::AsRawFd;
+use rackbox_notify::Notifier;
 
 fn daemonize() {
     // Fork the process
@@ -56,12 +57,12 @@ fn setup_logger() -> Result<(), syslog::Error> {
     let logger = syslog::unix(formatter)?;
     log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
         .map(|()| log::set_max_level(log::LevelFilter::Info))
-        .map_err(|e| syslog::Error::from(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))) // Corrigido
+        .map_err(|e| syslog::Error::from(std::io::Error::other(e.to_string()))) // Corrigido
 }
 
 fn main() {
-    // Daemonize the process
-    //daemonize();
+    // --foreground: stay attached for systemd (Type=notify) instead of forking
+    let foreground = std::env::args().skip(1).any(|arg| arg == "--foreground" || arg == "-f");
 
     // Set up syslog logging
     if let Err(e) = setup_logger() {
@@ -88,17 +89,26 @@ fn main() {
         }
     };
 
+    // Daemonize before starting the signal handler thread: threads don't
+    // survive fork()
+    if !foreground {
+        daemonize();
+    }
+
     // Create a flag to control the loop
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
 
-    // Handle Ctrl+C to stop the daemon gracefully
+    // Handle Ctrl+C / SIGTERM to stop the daemon gracefully
     ctrlc::set_handler(move || {
         r.store(false, Ordering::SeqCst);
         info!("Received termination signal, stopping daemon");
     }).expect("Error setting Ctrl+C handler");
 
-    daemonize();
+    let notifier = Notifier::from_env();
+    notifier.ready();
+    notifier.status("Blinking LED on GPIO 14");
+
     // Blink the LED 8 times per second
     while running.load(Ordering::SeqCst) {
         pin.set_high();
@@ -107,15 +117,13 @@ fn main() {
         thread::sleep(Duration::from_millis(240)); // 1/8 of a second
         pin.set_high();
         thread::sleep(Duration::from_millis(2400)); // 1/8 of a second
+        notifier.watchdog();
     }
 
-    loop{
-        thread::sleep(Duration::from_secs(1)); // Mantém o processo em execução
-    }
-   
+    notifier.stopping();
+
     // Clean up
     pin.set_low();
 
     info!("LED daemon stopped");
 }
-
@@ -3,8 +3,14 @@
 
 set -e
 
+if [ -f /etc/rackfan/config.toml ]; then
+    echo "Validando /etc/rackfan/config.toml"
+    target/release/rackfan_daemon --check-config /etc/rackfan/config.toml
+fi
+
 echo "Copiando o daemon"
 sudo cp target/release/rackfan_daemon /usr/local/bin/.
+sudo cp target/release/rackfanctl /usr/local/bin/.
 
 echo "Copiando rackfan.service "
 # Copie o arquivo de serviço
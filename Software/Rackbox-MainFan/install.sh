@@ -1,7 +1,7 @@
 #!/bin/bash
 
 # Compila o programa
-echo "Compiling rackfan-daemon..."
+echo "Compiling rackfan_daemon..."
 cargo build --release
 
 # Cria diretórios
@@ -9,17 +9,21 @@ sudo mkdir -p /etc/rackfan
 sudo mkdir -p /usr/local/bin
 
 # Instala binário
-sudo cp target/release/rackfan-daemon /usr/local/bin/
-sudo chmod +x /usr/local/bin/rackfan-daemon
+sudo cp target/release/rackfan_daemon /usr/local/bin/
+sudo chmod +x /usr/local/bin/rackfan_daemon
+sudo cp target/release/rackfanctl /usr/local/bin/
 
 # Instala arquivo de configuração
 if [ ! -f /etc/rackfan/config.toml ]; then
-    sudo cp config.toml.example /etc/rackfan/config.toml
+    sudo cp config.toml /etc/rackfan/config.toml
     echo "Please edit /etc/rackfan/config.toml with your settings"
 fi
 
+# Valida a configuração antes de (re)iniciar o serviço
+/usr/local/bin/rackfan_daemon --check-config /etc/rackfan/config.toml || exit 1
+
 # Instala serviço systemd
-sudo cp rackfan.service /etc/systemd/system/
+sudo cp etc/systemd/system/rackfan.service /etc/systemd/system/
 sudo systemctl daemon-reload
 
 echo "Installation complete!"
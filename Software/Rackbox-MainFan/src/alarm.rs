@@ -0,0 +1,218 @@
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::Problems;
+use crate::hook;
+
+/// `[alarm]` table: warning and critical levels above `temp_maxima`, for when
+/// the fan alone can't keep up. Each level is off unless its temperature is set.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmConfig {
+    pub warning_temp: Option<f32>,
+    pub critical_temp: Option<f32>,
+    /// Degrees below a level the temperature must drop before it clears.
+    pub hysteresis: Option<f32>,
+    /// Time after the hooks of a level ran before they may run again.
+    pub rearm_secs: Option<u64>,
+    /// Shell commands run when the warning level is reached.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warning_commands: Vec<String>,
+    /// Shell commands run when the critical level is reached, e.g. to shut
+    /// down non-essential machines.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub critical_commands: Vec<String>,
+}
+
+impl AlarmConfig {
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis.unwrap_or(2.0)
+    }
+
+    pub fn rearm(&self) -> Duration {
+        Duration::from_secs(self.rearm_secs.unwrap_or(1800))
+    }
+
+    /// Levels that aren't set stay off.
+    pub fn resolved(&self) -> Self {
+        AlarmConfig {
+            hysteresis: Some(self.hysteresis()),
+            rearm_secs: Some(self.rearm().as_secs()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        for (key, temp) in [("warning_temp", self.warning_temp), ("critical_temp", self.critical_temp)] {
+            if temp.is_some_and(|t| !t.is_finite()) {
+                problems.push(format_args!("{}.{}", path, key), "must be a finite number");
+            }
+        }
+        if let (Some(warning), Some(critical)) = (self.warning_temp, self.critical_temp)
+            && warning >= critical
+        {
+            problems.push(path, format_args!(
+                "warning_temp ({}) must be lower than critical_temp ({})", warning, critical));
+        }
+        if !(self.hysteresis().is_finite() && self.hysteresis() >= 0.0) {
+            problems.push(format_args!("{}.hysteresis", path), "must be 0 or more");
+        }
+
+        for (key, commands, temp) in [
+            ("warning_commands", &self.warning_commands, self.warning_temp),
+            ("critical_commands", &self.critical_commands, self.critical_temp),
+        ] {
+            if commands.iter().any(|c| c.trim().is_empty()) {
+                problems.push(format_args!("{}.{}", path, key), "commands must not be empty");
+            }
+            if !commands.is_empty() && temp.is_none() {
+                problems.push(format_args!("{}.{}", path, key),
+                              format_args!("needs {}", key.replace("_commands", "_temp")));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmLevel {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for AlarmLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlarmLevel::Normal => write!(f, "normal"),
+            AlarmLevel::Warning => write!(f, "warning"),
+            AlarmLevel::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Alarm level of one zone, driven by the control cycle's `dt`.
+#[derive(Debug, Default)]
+pub struct Alarm {
+    level: AlarmLevel,
+    /// Seconds since the alarm started.
+    clock: f32,
+    /// When the warning and critical hooks last ran.
+    fired: [Option<f32>; 2],
+}
+
+impl Alarm {
+    pub fn level(&self) -> AlarmLevel {
+        self.level
+    }
+
+    /// Level for `temp`: a level is entered at its temperature and left once
+    /// the temperature is `hysteresis` below it.
+    fn level_for(&self, config: &AlarmConfig, temp: f32) -> AlarmLevel {
+        let holds = |level: AlarmLevel, threshold: f32| {
+            temp >= threshold || (self.level >= level && temp > threshold - config.hysteresis())
+        };
+        if config.critical_temp.is_some_and(|t| holds(AlarmLevel::Critical, t)) {
+            AlarmLevel::Critical
+        } else if config.warning_temp.is_some_and(|t| holds(AlarmLevel::Warning, t)) {
+            AlarmLevel::Warning
+        } else {
+            AlarmLevel::Normal
+        }
+    }
+
+    /// Updates the level from the zone's control temperature, `None` when it
+    /// couldn't be read (the level is kept). Returns whether the hooks of a
+    /// newly reached level were due, i.e. not within the re-arm time.
+    pub fn update(&mut self, config: &AlarmConfig, zone: &str, temp: Option<f32>, dt: f32) -> bool {
+        self.clock += dt;
+        let Some(temp) = temp else {
+            return false;
+        };
+        let level = self.level_for(config, temp);
+        if level == self.level {
+            return false;
+        }
+        let previous = std::mem::replace(&mut self.level, level);
+
+        if level < previous {
+            info!("Zone '{}': {} alarm cleared at {:.1}°C, now {}", zone, previous, temp, level);
+            return false;
+        }
+
+        let (threshold, commands, slot) = match level {
+            AlarmLevel::Critical => {
+                let critical = config.critical_temp.unwrap_or(temp);
+                error!("Zone '{}': CRITICAL {:.1}°C reached the critical level of {:.1}°C",
+                       zone, temp, critical);
+                (critical, &config.critical_commands, 1)
+            }
+            _ => {
+                let warning = config.warning_temp.unwrap_or(temp);
+                warn!("Zone '{}': WARNING {:.1}°C reached the warning level of {:.1}°C",
+                      zone, temp, warning);
+                (warning, &config.warning_commands, 0)
+            }
+        };
+
+        let rearm = config.rearm().as_secs_f32();
+        if let Some(fired) = self.fired[slot]
+            && self.clock - fired < rearm
+        {
+            info!("Zone '{}': {} hooks ran {:.0}s ago, not run again for {:.0}s",
+                  zone, level, self.clock - fired, rearm - (self.clock - fired));
+            return false;
+        }
+        self.fired[slot] = Some(self.clock);
+        for command in commands {
+            hook::run_command(command, &[
+                ("RACKFAN_ZONE", zone.to_string()),
+                ("RACKFAN_LEVEL", level.to_string()),
+                ("RACKFAN_TEMP", format!("{:.1}", temp)),
+                ("RACKFAN_THRESHOLD", format!("{:.1}", threshold)),
+            ]);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_clear_with_hysteresis_and_hooks_rearm_after_cooldown() {
+        let config = AlarmConfig {
+            warning_temp: Some(40.0),
+            critical_temp: Some(45.0),
+            hysteresis: Some(2.0),
+            rearm_secs: Some(600),
+            ..AlarmConfig::default()
+        };
+        let mut alarm = Alarm::default();
+        assert!(!alarm.update(&config, "rack", Some(39.0), 5.0));
+
+        assert!(alarm.update(&config, "rack", Some(40.5), 5.0));
+        assert_eq!(alarm.level(), AlarmLevel::Warning);
+        assert!(alarm.update(&config, "rack", Some(46.0), 5.0));
+        assert_eq!(alarm.level(), AlarmLevel::Critical);
+
+        // Inside the hysteresis band the level holds; a failed read keeps it.
+        alarm.update(&config, "rack", Some(43.5), 5.0);
+        alarm.update(&config, "rack", None, 5.0);
+        assert_eq!(alarm.level(), AlarmLevel::Critical);
+        alarm.update(&config, "rack", Some(42.5), 5.0);
+        assert_eq!(alarm.level(), AlarmLevel::Warning);
+        alarm.update(&config, "rack", Some(37.5), 5.0);
+        assert_eq!(alarm.level(), AlarmLevel::Normal);
+
+        // Back up a minute later: logged, but the hooks wait for the re-arm.
+        assert!(!alarm.update(&config, "rack", Some(41.0), 60.0));
+        assert_eq!(alarm.level(), AlarmLevel::Warning);
+        alarm.update(&config, "rack", Some(37.0), 5.0);
+        assert!(alarm.update(&config, "rack", Some(41.0), 600.0));
+    }
+}
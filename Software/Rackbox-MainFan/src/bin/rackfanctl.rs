@@ -0,0 +1,187 @@
+//! Command-line client for the rackfan_daemon control socket.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+
+const DEFAULT_SOCKET: &str = "/run/rackfan/control.sock";
+
+const USAGE: &str = "\
+usage: rackfanctl [--socket <path>] [--json] [--for <duration>] <command>
+
+commands:
+  status                      sensors, zones and fan state
+  readings                    last reading of every sensor
+  fan                         fan state of every zone
+  on [zone]                   force the fan on (all zones if omitted)
+  off [zone]                  force the fan off
+  auto [zone]                 back to temperature control
+                              on/off last --for 90s, 30m, 2h (default and
+                              limit: [manual] max_duration_secs)
+  thresholds <min> <max> [zone]
+                              on/off thresholds until the next reload";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("rackfanctl: {:#}", e);
+            process::exit(2);
+        }
+    }
+}
+
+/// Returns whether the daemon accepted the request.
+fn run(args: &[String]) -> Result<bool> {
+    let mut socket = DEFAULT_SOCKET.to_string();
+    let mut as_json = false;
+    let mut duration = None;
+    let mut words = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => socket = args.next().context(USAGE)?.clone(),
+            "--json" => as_json = true,
+            "--for" => duration = Some(parse_duration(args.next().context(USAGE)?)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(true);
+            }
+            word => words.push(word),
+        }
+    }
+
+    let mut request = parse_command(&words)?;
+    if let Some(secs) = duration {
+        if request["command"] != "control" || request["mode"] == "auto" {
+            return Err(anyhow::anyhow!("--for only applies to on and off"));
+        }
+        request["duration_secs"] = json!(secs);
+    }
+    let response = send(&socket, &request)?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    } else if let Some(error) = response["error"].as_str() {
+        eprintln!("rackfanctl: {}", error);
+    } else {
+        print_human(&response);
+    }
+    Ok(response["ok"].as_bool().unwrap_or(false))
+}
+
+fn parse_command(words: &[&str]) -> Result<Value> {
+    let request = match words {
+        ["status"] | [] => json!({ "command": "status" }),
+        ["readings"] => json!({ "command": "readings" }),
+        ["fan"] => json!({ "command": "fan" }),
+        [mode @ ("on" | "off" | "auto"), zone @ ..] if zone.len() <= 1 => {
+            json!({ "command": "control", "mode": mode, "zone": zone.first() })
+        }
+        ["thresholds", min, max, zone @ ..] if zone.len() <= 1 => {
+            let min: f32 = min.parse().with_context(|| format!("invalid minimum '{}'", min))?;
+            let max: f32 = max.parse().with_context(|| format!("invalid maximum '{}'", max))?;
+            json!({
+                "command": "thresholds",
+                "temp_minima": min,
+                "temp_maxima": max,
+                "zone": zone.first(),
+            })
+        }
+        _ => return Err(anyhow::anyhow!("{}", USAGE)),
+    };
+    Ok(request)
+}
+
+/// Seconds in `90`, `90s`, `30m` or `2h`.
+fn parse_duration(text: &str) -> Result<u64> {
+    let (number, unit) = match text.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let number: u64 = number.parse()
+        .with_context(|| format!("invalid duration '{}'", text))?;
+    match unit {
+        "s" => Ok(number),
+        "m" => Ok(number * 60),
+        "h" => Ok(number * 3600),
+        _ => Err(anyhow::anyhow!("invalid duration '{}', use e.g. 90s, 30m or 2h", text)),
+    }
+}
+
+fn send(socket: &str, request: &Value) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("cannot connect to {} (is rackfan_daemon running?)", socket))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)
+        .context("failed to read the daemon's reply")?;
+    serde_json::from_str(&line).context("invalid reply from daemon")
+}
+
+fn print_human(response: &Value) {
+    if let Some(uptime) = response["uptime_secs"].as_u64() {
+        println!("rackfan_daemon up {}h{:02}m, config {}, every {}s",
+                 uptime / 3600, uptime / 60 % 60,
+                 response["config"].as_str().unwrap_or("?"),
+                 response["check_interval_secs"]);
+    }
+
+    if let Some(sensors) = response["sensors"].as_array() {
+        println!("sensors:");
+        for sensor in sensors {
+            let name = sensor["name"].as_str().unwrap_or("?");
+            let mut environment = String::new();
+            if let Some(humidity) = sensor["humidity"].as_f64() {
+                environment.push_str(&format!("  {:>3.0}% RH", humidity));
+            }
+            if let Some(dew_point) = sensor["dew_point"].as_f64() {
+                environment.push_str(&format!("  dew point {:.1}°C", dew_point));
+            }
+            if let Some(pressure) = sensor["pressure"].as_f64() {
+                environment.push_str(&format!("  {:.1} hPa", pressure));
+            }
+            match sensor["temperature"].as_f64() {
+                Some(temp) => println!("  {:<16} {:>6.1}°C{}", name, temp, environment),
+                None => println!("  {:<16} error: {}", name,
+                                 sensor["error"].as_str().unwrap_or("no reading yet")),
+            }
+        }
+    }
+
+    if let Some(zones) = response["zones"].as_array() {
+        println!("zones:");
+        for zone in zones {
+            let temp = zone["temperature"].as_f64()
+                .map_or("  error".to_string(), |t| format!("{:>5.1}°C", t));
+            let fan = if zone["fan_on"].as_bool().unwrap_or(false) { "ON " } else { "OFF" };
+            let rpm = zone["rpm"].as_f64().map_or(String::new(), |r| format!(" {:.0} RPM", r));
+            let left = zone["control_remaining_secs"].as_u64()
+                .map_or(String::new(), |secs| format!(" ({}m{:02}s left)", secs / 60, secs % 60));
+            let profile = match (zone["profile"].as_str(), zone["profile_suspended"].as_bool()) {
+                (Some(name), Some(true)) => format!(", profile {} suspended", name),
+                (Some(name), _) => format!(", profile {}", name),
+                (None, _) => String::new(),
+            };
+            println!("  {:<16} {}  fan {} {:>3.0}%{}  control {}{}  {}  [{}-{}°C, {}{}]",
+                     zone["name"].as_str().unwrap_or("?"), temp, fan,
+                     zone["duty"].as_f64().unwrap_or(0.0), rpm,
+                     zone["control"].as_str().unwrap_or("?"), left,
+                     zone["health"].as_str().unwrap_or("?"),
+                     zone["temp_minima"], zone["temp_maxima"],
+                     zone["fan_mode"].as_str().unwrap_or("?"), profile);
+            if let Some(level) = zone["alarm"].as_str().filter(|level| *level != "normal") {
+                println!("  {:<16} alarm: {}", "", level.to_uppercase());
+            }
+            if let Some(reason) = zone["last_reason"].as_str() {
+                println!("  {:<16} last change: {}", "", reason);
+            }
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs;
+use std::time::Duration;
+
+use crate::alarm::AlarmConfig;
+use crate::control;
+use crate::cycling::CyclingConfig;
+use crate::failsafe::FailsafeConfig;
+use crate::fan::{FanMode, PwmConfig};
+use crate::filter::FilterConfig;
+use crate::history::HistoryConfig;
+use crate::hook::HooksConfig;
+use crate::humidity::HumidityConfig;
+use crate::i2c::I2cSensorConfig;
+use crate::manual::ManualConfig;
+use crate::mqtt::MqttConfig;
+use crate::pid::PidConfig;
+use crate::profile::ProfileConfig;
+use crate::sensor::{self, check_resolution, OneWireConfig, SensorConfig, SensorPolicy};
+use crate::sysfs::SysfsSensorConfig;
+use crate::tach::TachConfig;
+use crate::watchdog::WatchdogConfig;
+use crate::zone::{ZoneConfig, ZoneSettings};
+
+/// Highest BCM GPIO number on the Raspberry Pi 40-pin header.
+const MAX_BCM_GPIO: u8 = 27;
+
+/// Pins claimed by the other Rackbox daemons.
+const RESERVED_GPIOS: &[(u8, &str)] = &[(14, "the LED daemon (led_daemon)")];
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub temp_minima: f32,
+    pub temp_maxima: f32,
+    pub fan_gpio: Option<u8>,
+    pub sensor_path: Option<String>,
+    pub check_interval_secs: Option<u64>,
+    /// Unix socket for `rackfanctl`.
+    pub control_socket: Option<String>,
+    /// `host:port` for the Prometheus `/metrics` endpoint; off when unset.
+    pub metrics_listen: Option<String>,
+    #[serde(default)]
+    pub fan_mode: FanMode,
+    pub pwm: Option<PwmConfig>,
+    pub pid: Option<PidConfig>,
+    #[serde(default)]
+    pub sensor_policy: SensorPolicy,
+    #[serde(default, rename = "sensor", skip_serializing_if = "Vec::is_empty")]
+    pub sensors: Vec<SensorConfig>,
+    #[serde(default, rename = "i2c_sensor", skip_serializing_if = "Vec::is_empty")]
+    pub i2c_sensors: Vec<I2cSensorConfig>,
+    #[serde(default, rename = "sysfs_sensor", skip_serializing_if = "Vec::is_empty")]
+    pub sysfs_sensors: Vec<SysfsSensorConfig>,
+    #[serde(default)]
+    pub onewire: OneWireConfig,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default)]
+    pub failsafe: FailsafeConfig,
+    #[serde(default)]
+    pub cycling: CyclingConfig,
+    #[serde(default)]
+    pub alarm: AlarmConfig,
+    #[serde(default)]
+    pub humidity: HumidityConfig,
+    #[serde(default)]
+    pub manual: ManualConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default, rename = "zone")]
+    pub zones: Vec<ZoneConfig>,
+    #[serde(default, rename = "profile", skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<ProfileConfig>,
+    pub tach: Option<TachConfig>,
+    pub watchdog: Option<WatchdogConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub history: Option<HistoryConfig>,
+}
+
+impl Config {
+    /// Reads, parses and validates a config file. Unknown keys are rejected
+    /// and every invalid field is reported, not just the first one.
+    pub fn load(path: &str) -> Result<Self> {
+        let config_content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path))?;
+        Self::parse(&config_content, path)
+    }
+
+    /// Parses and validates config.toml contents; `path` is only for messages.
+    fn parse(config_content: &str, path: &str) -> Result<Self> {
+        let mut config: Config = toml::from_str(config_content)
+            .with_context(|| "Failed to parse config file")?;
+
+        let curves = config.pwm.iter_mut()
+            .chain(config.zones.iter_mut().filter_map(|z| z.pwm.as_mut()));
+        for pwm in curves {
+            pwm.curve.sort_by(|a, b| a.temp.total_cmp(&b.temp));
+        }
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(anyhow::anyhow!("Invalid config {}:\n{}", path, problems));
+        }
+
+        Ok(config)
+    }
+
+    pub fn default() -> Self {
+        Config {
+            temp_minima: 25.0,
+            temp_maxima: 35.0,
+            fan_gpio: Some(17),
+            sensor_path: None,
+            check_interval_secs: Some(5),
+            control_socket: None,
+            metrics_listen: None,
+            fan_mode: FanMode::OnOff,
+            pwm: None,
+            pid: None,
+            sensor_policy: SensorPolicy::Max,
+            sensors: Vec::new(),
+            i2c_sensors: Vec::new(),
+            sysfs_sensors: Vec::new(),
+            onewire: OneWireConfig::default(),
+            filter: FilterConfig::default(),
+            failsafe: FailsafeConfig::default(),
+            cycling: CyclingConfig::default(),
+            alarm: AlarmConfig::default(),
+            humidity: HumidityConfig::default(),
+            manual: ManualConfig::default(),
+            hooks: HooksConfig::default(),
+            zones: Vec::new(),
+            profiles: Vec::new(),
+            tach: None,
+            watchdog: None,
+            mqtt: None,
+            history: None,
+        }
+    }
+
+    pub fn check_interval_secs(&self) -> u64 {
+        self.check_interval_secs.unwrap_or(5)
+    }
+
+    pub fn control_socket(&self) -> &str {
+        self.control_socket.as_deref().unwrap_or(control::DEFAULT_SOCKET)
+    }
+
+    /// Longest one cycle can spend reading sensors: every read rejected and
+    /// retried as often as `[filter]` allows. Probes on the bus that have no
+    /// `[[sensor]]` entry can't be counted here; without entries one is assumed.
+    fn worst_case_read_time(&self) -> Duration {
+        let onewire: Duration = if self.sensors.is_empty() {
+            sensor::conversion_time(self.onewire.resolution)
+        } else {
+            self.sensors.iter()
+                .map(|s| sensor::conversion_time(s.resolution.or(self.onewire.resolution)))
+                .sum()
+        };
+        let i2c: Duration = self.i2c_sensors.iter().map(|s| s.kind.conversion_time()).sum();
+        (onewire + i2c) * (self.filter.retries() + 1)
+    }
+
+    fn validate(&self) -> Problems {
+        let mut problems = Problems::default();
+
+        if self.check_interval_secs == Some(0) {
+            problems.push("check_interval_secs", "must be at least 1");
+        }
+        check_thresholds(&mut problems, "", self.temp_minima, self.temp_maxima);
+        if self.control_socket.as_deref().is_some_and(|p| !p.starts_with('/')) {
+            problems.push("control_socket", "must be an absolute path");
+        }
+        if let Some(addr) = &self.metrics_listen
+            && addr.parse::<std::net::SocketAddr>().is_err()
+        {
+            problems.push("metrics_listen",
+                          format_args!("'{}' is not an address like 0.0.0.0:9101", addr));
+        }
+
+        if let Some(pin) = self.fan_gpio {
+            check_gpio(&mut problems, "fan_gpio", pin);
+        } else if self.zones.is_empty() {
+            problems.push("fan_gpio", "required when no [[zone]] is configured");
+        }
+        if let Some(pwm) = &self.pwm {
+            pwm.validate("pwm", &mut problems);
+        }
+        if let Some(pid) = &self.pid {
+            pid.validate("pid", &mut problems);
+        }
+        if let Some(tach) = &self.tach {
+            tach.validate("tach", &mut problems);
+        }
+        self.onewire.validate("onewire", &mut problems);
+        self.filter.validate("filter", &mut problems);
+        self.failsafe.validate("failsafe", &mut problems);
+        self.cycling.validate("cycling", &mut problems);
+        self.alarm.validate("alarm", &mut problems);
+        self.humidity.validate("humidity", &mut problems);
+        self.manual.validate("manual", &mut problems);
+        self.hooks.validate("hooks", &mut problems);
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.validate("watchdog", self.check_interval_secs(), self.worst_case_read_time(),
+                              &mut problems);
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate("mqtt", &mut problems);
+        }
+        if let Some(history) = &self.history {
+            history.validate("history", &mut problems);
+        }
+
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            let path = format!("sensor[{}]", i);
+            if sensor.id.trim().is_empty() {
+                problems.push(format_args!("{}.id", path), "must not be empty");
+            }
+            if sensor.name.trim().is_empty() {
+                problems.push(format_args!("{}.name", path), "must not be empty");
+            }
+            check_resolution(&mut problems, format_args!("{}.resolution", path), sensor.resolution);
+            if let Some(j) = self.sensors[..i].iter().position(|s| s.id == sensor.id) {
+                problems.push(format_args!("{}.id", path),
+                              format_args!("'{}' already used by sensor[{}]", sensor.id, j));
+            }
+            if let Some(j) = self.sensors[..i].iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by sensor[{}]", sensor.name, j));
+            }
+        }
+
+        for (i, sensor) in self.i2c_sensors.iter().enumerate() {
+            let path = format!("i2c_sensor[{}]", i);
+            sensor.validate(&path, &mut problems);
+            if let Some(j) = self.sensors.iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by sensor[{}]", sensor.name, j));
+            }
+            if let Some(j) = self.i2c_sensors[..i].iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by i2c_sensor[{}]", sensor.name, j));
+            }
+            if let Some(j) = self.i2c_sensors[..i].iter()
+                .position(|s| (s.bus(), s.address()) == (sensor.bus(), sensor.address()))
+            {
+                problems.push(format_args!("{}.address", path), format_args!(
+                    "{:#04x} on bus {} already used by i2c_sensor[{}]",
+                    sensor.address(), sensor.bus(), j));
+            }
+        }
+
+        for (i, sensor) in self.sysfs_sensors.iter().enumerate() {
+            let path = format!("sysfs_sensor[{}]", i);
+            sensor.validate(&path, &mut problems);
+            if let Some(j) = self.sensors.iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by sensor[{}]", sensor.name, j));
+            }
+            if let Some(j) = self.i2c_sensors.iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by i2c_sensor[{}]", sensor.name, j));
+            }
+            if let Some(j) = self.sysfs_sensors[..i].iter().position(|s| s.name == sensor.name) {
+                problems.push(format_args!("{}.name", path),
+                              format_args!("'{}' already used by sysfs_sensor[{}]", sensor.name, j));
+            }
+        }
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            zone.validate(&format!("zone[{}]", i), &mut problems);
+        }
+
+        for (i, profile) in self.profiles.iter().enumerate() {
+            profile.validate(&format!("profile[{}]", i), &mut problems);
+            if self.profiles[..i].iter().any(|other| other.name == profile.name) {
+                problems.push(format_args!("profile[{}].name", i),
+                              format_args!("duplicate profile name '{}'", profile.name));
+            }
+        }
+
+        if let Ok(zones) = self.zones() {
+            self.validate_zones(&zones, &mut problems);
+            for (i, profile) in self.profiles.iter().enumerate() {
+                profile.validate_zones(&format!("profile[{}]", i), &zones, &mut problems);
+            }
+        }
+
+        problems
+    }
+
+    /// Checks on the effective zones, after top-level defaults are applied.
+    fn validate_zones(&self, zones: &[ZoneSettings], problems: &mut Problems) {
+        for (i, zone) in zones.iter().enumerate() {
+            let path = if self.zones.is_empty() { String::new() } else { format!("zone[{}].", i) };
+
+            if self.zones.get(i).is_some_and(|z| z.temp_minima.is_some() || z.temp_maxima.is_some()) {
+                check_thresholds(problems, &path, zone.temp_minima, zone.temp_maxima);
+            }
+
+            match zone.fan_mode {
+                FanMode::Pwm => match &zone.pwm {
+                    None => problems.push(format_args!("{}fan_mode", path),
+                                          "\"pwm\" requires a [pwm] table"),
+                    Some(pwm) if pwm.curve.is_empty() => problems.push(
+                        format_args!("{}pwm.curve", path),
+                        "must have at least one point in fan_mode = \"pwm\""),
+                    Some(_) => {}
+                },
+                FanMode::Pid if zone.pid.is_none() => {
+                    problems.push(format_args!("{}fan_mode", path),
+                                  "\"pid\" requires a [pid] table");
+                }
+                _ => {}
+            }
+
+            for other in &zones[..i] {
+                if other.name == zone.name {
+                    problems.push(format_args!("{}name", path),
+                                  format_args!("duplicate zone name '{}'", zone.name));
+                }
+                if other.fan_gpio == zone.fan_gpio {
+                    problems.push(format_args!("{}fan_gpio", path),
+                                  format_args!("GPIO {} is already the fan of zone '{}'",
+                                               zone.fan_gpio, other.name));
+                }
+                if let Some(tach) = &other.tach
+                    && tach.gpio == zone.fan_gpio
+                {
+                    problems.push(format_args!("{}fan_gpio", path),
+                                  format_args!("GPIO {} is the tach input of zone '{}'",
+                                               zone.fan_gpio, other.name));
+                }
+                if let Some(tach) = &zone.tach
+                    && (tach.gpio == other.fan_gpio
+                        || other.tach.as_ref().is_some_and(|t| t.gpio == tach.gpio))
+                {
+                    problems.push(format_args!("{}tach.gpio", path),
+                                  format_args!("GPIO {} is already used by zone '{}'",
+                                               tach.gpio, other.name));
+                }
+                if let (Some(a), Some(b)) = (hardware_channel(zone), hardware_channel(other))
+                    && a == b
+                {
+                    problems.push(format_args!("{}pwm.channel", path),
+                                  format_args!("PWM channel {} is already used by zone '{}'",
+                                               a, other.name));
+                }
+            }
+
+            if let Some(tach) = &zone.tach
+                && tach.gpio == zone.fan_gpio
+            {
+                problems.push(format_args!("{}tach.gpio", path),
+                              format_args!("GPIO {} is the fan GPIO", tach.gpio));
+            }
+        }
+    }
+
+    /// Effective zones. Without `[[zone]]` tables the top-level settings form
+    /// a single zone covering all sensors.
+    pub fn zones(&self) -> Result<Vec<ZoneSettings>> {
+        if self.zones.is_empty() {
+            let fan_gpio = self.fan_gpio
+                .context("fan_gpio is required when no [[zone]] is configured")?;
+            return Ok(vec![ZoneSettings {
+                name: "rack".to_string(),
+                sensors: Vec::new(),
+                fan_gpio,
+                temp_minima: self.temp_minima,
+                temp_maxima: self.temp_maxima,
+                fan_mode: self.fan_mode,
+                pwm: self.pwm.clone(),
+                pid: self.pid.clone(),
+                sensor_policy: self.sensor_policy.clone(),
+                failsafe: self.failsafe.clone(),
+                cycling: self.cycling.clone(),
+                alarm: self.alarm.clone(),
+                humidity: self.humidity.clone(),
+                manual: self.manual.clone(),
+                hooks: self.hooks.clone(),
+                tach: self.tach.clone(),
+            }]);
+        }
+
+        Ok(self.zones.iter()
+            .map(|zone| ZoneSettings {
+                name: zone.name.clone(),
+                sensors: zone.sensors.clone(),
+                fan_gpio: zone.fan_gpio,
+                temp_minima: zone.temp_minima.unwrap_or(self.temp_minima),
+                temp_maxima: zone.temp_maxima.unwrap_or(self.temp_maxima),
+                fan_mode: zone.fan_mode.unwrap_or(self.fan_mode),
+                pwm: zone.pwm.clone().or_else(|| self.pwm.clone()),
+                pid: zone.pid.clone().or_else(|| self.pid.clone()),
+                sensor_policy: zone.sensor_policy.clone()
+                    .unwrap_or_else(|| self.sensor_policy.clone()),
+                failsafe: zone.failsafe.clone().unwrap_or_else(|| self.failsafe.clone()),
+                cycling: zone.cycling.clone().unwrap_or_else(|| self.cycling.clone()),
+                alarm: zone.alarm.clone().unwrap_or_else(|| self.alarm.clone()),
+                humidity: zone.humidity.clone().unwrap_or_else(|| self.humidity.clone()),
+                manual: self.manual.clone(),
+                hooks: self.hooks.clone(),
+                tach: zone.tach.clone(),
+            })
+            .collect())
+    }
+
+    /// The configuration as the daemon will run it: every zone written out
+    /// with what it inherits from the top level, and every default filled in.
+    pub fn effective(&self) -> Result<Config> {
+        let zones = self.zones()?.iter().map(|zone| zone.resolved().into()).collect();
+        Ok(Config {
+            temp_minima: self.temp_minima,
+            temp_maxima: self.temp_maxima,
+            fan_gpio: self.fan_gpio,
+            sensor_path: self.sensor_path.clone(),
+            check_interval_secs: Some(self.check_interval_secs()),
+            control_socket: Some(self.control_socket().to_string()),
+            metrics_listen: self.metrics_listen.clone(),
+            fan_mode: self.fan_mode,
+            pwm: self.pwm.as_ref().map(PwmConfig::resolved),
+            pid: self.pid.as_ref().map(PidConfig::resolved),
+            sensor_policy: self.sensor_policy.clone(),
+            sensors: self.sensors.clone(),
+            i2c_sensors: self.i2c_sensors.iter().map(I2cSensorConfig::resolved).collect(),
+            sysfs_sensors: self.sysfs_sensors.clone(),
+            onewire: self.onewire.resolved(),
+            filter: self.filter.resolved(),
+            failsafe: self.failsafe.resolved(),
+            cycling: self.cycling.resolved(),
+            alarm: self.alarm.resolved(),
+            humidity: self.humidity.resolved(),
+            manual: self.manual.resolved(),
+            hooks: self.hooks.resolved(),
+            zones,
+            profiles: self.profiles.clone(),
+            tach: self.tach.as_ref().map(TachConfig::resolved),
+            watchdog: self.watchdog.as_ref().map(WatchdogConfig::resolved),
+            mqtt: self.mqtt.as_ref().map(MqttConfig::resolved),
+            history: self.history.as_ref().map(HistoryConfig::resolved),
+        })
+    }
+
+    /// [`Config::effective`] in config.toml syntax.
+    pub fn effective_toml(&self) -> Result<String> {
+        let mut value = toml::Value::try_from(self.effective()?)?;
+        shorten_floats(&mut value);
+        Ok(toml::to_string(&value)?)
+    }
+}
+
+/// Every number here is read as an `f32` (`frequency_hz` is the one `f64`).
+/// Serialized through `f64`, 0.2 would print as 0.20000000298023224; write
+/// such values as the shortest decimal that reads back as the same `f32`.
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(float) if *float as f32 as f64 == *float => {
+            *float = (*float as f32).to_string().parse().unwrap_or(*float);
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(shorten_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| shorten_floats(value)),
+        _ => {}
+    }
+}
+
+fn hardware_channel(zone: &ZoneSettings) -> Option<u8> {
+    match zone.fan_mode {
+        FanMode::OnOff => None,
+        _ => zone.pwm.as_ref().and_then(|pwm| pwm.channel),
+    }
+}
+
+fn check_thresholds(problems: &mut Problems, path: &str, minima: f32, maxima: f32) {
+    if !minima.is_finite() || !maxima.is_finite() {
+        problems.push(format_args!("{}temp_minima", path), "thresholds must be finite numbers");
+    } else if minima >= maxima {
+        problems.push(format_args!("{}temp_minima", path),
+                      format_args!("must be lower than temp_maxima ({} >= {})", minima, maxima));
+    }
+}
+
+/// Checks that `pin` is a usable BCM GPIO not owned by another daemon.
+pub fn check_gpio(problems: &mut Problems, field: impl Display, pin: u8) {
+    if pin > MAX_BCM_GPIO {
+        problems.push(field, format_args!("GPIO {} is not a valid BCM pin (0-{})",
+                                          pin, MAX_BCM_GPIO));
+    } else if let Some((_, owner)) = RESERVED_GPIOS.iter().find(|(p, _)| *p == pin) {
+        problems.push(field, format_args!("GPIO {} is used by {}", pin, owner));
+    }
+}
+
+/// Every problem found while validating a config, one per field.
+#[derive(Default)]
+pub struct Problems(Vec<String>);
+
+impl Problems {
+    pub fn push(&mut self, field: impl Display, message: impl Display) {
+        self.0.push(format!("{}: {}", field, message));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: &str = r#"
+temp_minima = 25.0
+temp_maxima = 30.0
+fan_gpio = 27
+metrics_listen = "0.0.0.0:9101"
+fan_mode = "pwm"
+
+[pwm]
+min_duty = 20.0
+curve = [{ temp = 35.0, duty = 100.0 }, { temp = 25.0, duty = 20.0 }]
+
+[[sensor]]
+id = "28-0316a2794fff"
+name = "inlet"
+resolution = 12
+
+[[sysfs_sensor]]
+name = "ssd"
+source = "hwmon:nvme/Composite"
+
+[sensor_policy]
+kind = "sensor"
+name = "ssd"
+
+[alarm]
+critical_temp = 45.0
+critical_commands = ["logger hot"]
+
+[hooks]
+on_fan_on = "logger on"
+
+[watchdog]
+timeout_secs = 15
+
+[mqtt]
+host = "192.168.1.10"
+username = "rackfan"
+password = "segredo"
+
+[[zone]]
+name = "topo"
+fan_gpio = 22
+tach = { gpio = 23 }
+
+[[zone]]
+name = "fonte"
+sensors = ["inlet"]
+fan_gpio = 24
+fan_mode = "pid"
+pid = { setpoint = 28.0, kp = 8.0, ki = 0.2, kd = 0.0 }
+
+[[zone]]
+name = "porta"
+fan_gpio = 25
+fan_mode = "onoff"
+cycling = { min_on_secs = 120 }
+
+[[profile]]
+name = "noite"
+start = "23:00"
+end = "07:00"
+max_duty = 60.0
+"#;
+
+    /// Every problem reported for a config, or "" when it is valid.
+    fn problems(text: &str) -> String {
+        match Config::parse(text, "test.toml") {
+            Ok(_) => String::new(),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = problems("temp_minima = 25.0\ntemp_maxma = 30.0\nfan_gpio = 17\n");
+        assert!(error.contains("unknown field `temp_maxma`"), "{}", error);
+
+        let error = problems("temp_minima = 25.0\ntemp_maxima = 30.0\nfan_gpio = 17\n\
+                              [filter]\nretry = 2\n");
+        assert!(error.contains("unknown field `retry`"), "{}", error);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let error = problems("temp_minima = 30.0\ntemp_maxima = 25.0\nfan_gpio = 40\n\
+                              check_interval_secs = 0\n");
+        assert!(error.starts_with("Invalid config test.toml:"), "{}", error);
+        for expected in [
+            "check_interval_secs: must be at least 1",
+            "temp_minima: must be lower than temp_maxima (30 >= 25)",
+            "fan_gpio: GPIO 40 is not a valid BCM pin (0-27)",
+        ] {
+            assert!(error.contains(expected), "missing '{}' in:\n{}", expected, error);
+        }
+    }
+
+    #[test]
+    fn gpios_of_other_daemons_and_zones_are_refused() {
+        let error = problems("temp_minima = 25.0\ntemp_maxima = 30.0\nfan_gpio = 14\n");
+        assert!(error.contains("fan_gpio: GPIO 14 is used by the LED daemon"), "{}", error);
+
+        let error = problems("temp_minima = 25.0\ntemp_maxima = 30.0\n\
+                              [[zone]]\nname = \"a\"\nfan_gpio = 22\n\
+                              [[zone]]\nname = \"b\"\nfan_gpio = 22\n");
+        assert!(error.contains("zone[1].fan_gpio: GPIO 22 is already the fan of zone 'a'"),
+                "{}", error);
+    }
+
+    #[test]
+    fn a_minimal_config_is_valid() {
+        assert_eq!(problems("temp_minima = 25.0\ntemp_maxima = 30.0\nfan_gpio = 17\n"), "");
+        let error = problems("temp_minima = 25.0\ntemp_maxima = 30.0\n");
+        assert!(error.contains("fan_gpio: required when no [[zone]] is configured"), "{}", error);
+    }
+
+    #[test]
+    fn watchdog_timeout_covers_slow_sensor_reads() {
+        let config = |probes: usize| {
+            let mut text = String::from("temp_minima = 25.0\ntemp_maxima = 30.0\nfan_gpio = 17\n\
+                                         [watchdog]\ntimeout_secs = 15\n");
+            for i in 0..probes {
+                text.push_str(&format!("[[sensor]]\nid = \"28-{0}\"\nname = \"p{0}\"\n", i));
+            }
+            problems(&text)
+        };
+        // 5s interval + 4 probes x 750 ms x 3 attempts = 14s.
+        assert_eq!(config(4), "");
+        // 5s + 11.25s.
+        let error = config(5);
+        assert!(error.contains("watchdog.timeout_secs: must be longer than check_interval_secs (5) \
+                                plus the worst-case sensor read time (11.25s)"), "{}", error);
+    }
+
+    #[test]
+    fn effective_toml_loads_back_to_the_same_config() {
+        let config = Config::parse(FULL, "full.toml").unwrap();
+        let text = config.effective_toml().unwrap();
+        let reloaded = Config::parse(&text, "effective.toml")
+            .unwrap_or_else(|e| panic!("{:#}\n{}", e, text));
+        assert_eq!(reloaded, config.effective().unwrap(), "{}", text);
+
+        let zones: Vec<ZoneSettings> = config.zones().unwrap().iter()
+            .map(ZoneSettings::resolved)
+            .collect();
+        assert_eq!(reloaded.zones().unwrap(), zones);
+        assert!(text.contains("ki = 0.2\n"), "{}", text);
+    }
+}
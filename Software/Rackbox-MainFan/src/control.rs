@@ -0,0 +1,216 @@
+//! Control socket: newline-delimited JSON requests on a Unix socket,
+//! answered by the control loop between cycles. `rackfanctl` is the client.
+//!
+//! Requests look like `{"command": "status"}` or
+//! `{"command": "control", "zone": "rack", "mode": "on", "duration_secs": 1800}`;
+//! every reply has `"ok"` and either the requested data or `"error"`.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::i2c;
+use crate::sensor::SensorReading;
+use crate::zone::{Control, Zone};
+use crate::Daemon;
+
+pub const DEFAULT_SOCKET: &str = "/run/rackfan/control.sock";
+
+/// How long a client waits for the control loop, which may be in the middle
+/// of reading slow 1-Wire sensors.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Everything: sensors, zones and daemon state.
+    Status,
+    /// Last reading of every sensor.
+    Readings,
+    /// State of every fan.
+    Fan,
+    /// Force a zone (all zones when `zone` is omitted) on/off for
+    /// `duration_secs` (the `[manual]` maximum if omitted), or back to auto.
+    Control { zone: Option<String>, mode: Control, duration_secs: Option<u64> },
+    /// Thresholds in effect until the next reload or restart.
+    Thresholds { zone: Option<String>, temp_minima: f32, temp_maxima: f32 },
+}
+
+/// Sensor reading as reported over the socket.
+#[derive(Serialize)]
+pub struct SensorStatus {
+    pub name: String,
+    pub temperature: Option<f32>,
+    /// Relative humidity (%), pressure (hPa) and dew point (°C) from
+    /// environmental sensors.
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    pub dew_point: Option<f32>,
+    pub error: Option<String>,
+}
+
+impl From<&SensorReading> for SensorStatus {
+    fn from(reading: &SensorReading) -> Self {
+        let temperature = reading.temp.as_ref().ok().copied();
+        let humidity = reading.environment.humidity;
+        SensorStatus {
+            name: reading.name.clone(),
+            temperature,
+            humidity,
+            pressure: reading.environment.pressure,
+            dew_point: temperature.zip(humidity).map(|(t, h)| i2c::dew_point(t, h)),
+            error: reading.temp.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+/// A parsed request waiting for the control loop, with the channel back to
+/// the client connection.
+pub struct Pending {
+    pub request: Result<Request, String>,
+    pub reply: Sender<Value>,
+}
+
+pub struct ControlServer {
+    path: PathBuf,
+    requests: Receiver<Pending>,
+}
+
+impl ControlServer {
+    /// Binds the socket, replacing a stale one left by a previous run, and
+    /// accepts clients on a background thread.
+    pub fn bind(path: &str) -> Result<Self> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        if Path::new(path).exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {}", path))?;
+
+        let (tx, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let tx = tx.clone();
+                        thread::spawn(move || serve_client(stream, tx));
+                    }
+                    Err(e) => warn!("Control socket accept failed: {}", e),
+                }
+            }
+        });
+
+        info!("Control socket listening on {}", path);
+        Ok(ControlServer { path: PathBuf::from(path), requests })
+    }
+
+    /// Next client request, waiting at most `timeout`.
+    pub fn next(&self, timeout: Duration) -> Option<Pending> {
+        self.requests.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve_client(stream: UnixStream, requests: Sender<Pending>) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut line = String::new();
+    let mut reader = BufReader::new(&stream);
+    if let Err(e) = reader.read_line(&mut line) {
+        warn!("Control socket read failed: {}", e);
+        return;
+    }
+
+    let (reply, response) = mpsc::channel();
+    let request = serde_json::from_str(&line).map_err(|e| format!("invalid request: {}", e));
+    if requests.send(Pending { request, reply }).is_err() {
+        return;
+    }
+
+    let response = response.recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| error("daemon did not answer in time"));
+    let mut writer = &stream;
+    if let Err(e) = writeln!(writer, "{}", response) {
+        warn!("Control socket write failed: {}", e);
+    }
+}
+
+fn error(message: impl std::fmt::Display) -> Value {
+    json!({ "ok": false, "error": message.to_string() })
+}
+
+impl Daemon {
+    pub(crate) fn handle_request(&mut self, request: Result<Request, String>) -> Value {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return error(e),
+        };
+
+        match request {
+            Request::Status => json!({
+                "ok": true,
+                "uptime_secs": self.started.elapsed().as_secs(),
+                "config": self.config_path,
+                "check_interval_secs": self.config.check_interval_secs(),
+                "sensors": self.readings,
+                "zones": self.zone_statuses(None),
+            }),
+            Request::Readings => json!({ "ok": true, "sensors": self.readings }),
+            Request::Fan => json!({ "ok": true, "zones": self.zone_statuses(None) }),
+            Request::Control { zone, mode, duration_secs } => {
+                let duration = duration_secs.map(Duration::from_secs);
+                let result = self.selected_zones(zone.as_deref()).and_then(|zones| {
+                    zones.into_iter().try_for_each(|zone| zone.set_control(mode, duration))
+                });
+                match result {
+                    Ok(()) => json!({ "ok": true, "zones": self.zone_statuses(zone.as_deref()) }),
+                    Err(e) => error(e),
+                }
+            }
+            Request::Thresholds { zone, temp_minima, temp_maxima } => {
+                let result = self.selected_zones(zone.as_deref()).and_then(|zones| {
+                    zones.into_iter()
+                        .try_for_each(|zone| zone.set_thresholds(temp_minima, temp_maxima))
+                });
+                match result {
+                    Ok(()) => json!({ "ok": true, "zones": self.zone_statuses(zone.as_deref()) }),
+                    Err(e) => error(e),
+                }
+            }
+        }
+    }
+
+    fn selected_zones(&mut self, name: Option<&str>) -> Result<Vec<&mut Zone>> {
+        let zones: Vec<&mut Zone> = self.zones.iter_mut()
+            .filter(|zone| name.is_none_or(|name| zone.settings.name == name))
+            .collect();
+        match (name, zones.is_empty()) {
+            (Some(name), true) => Err(anyhow::anyhow!("no zone named '{}'", name)),
+            _ => Ok(zones),
+        }
+    }
+
+    fn zone_statuses(&self, name: Option<&str>) -> Value {
+        let statuses: Vec<_> = self.zones.iter()
+            .filter(|zone| name.is_none_or(|name| zone.settings.name == name))
+            .map(Zone::status)
+            .collect();
+        json!(statuses)
+    }
+}
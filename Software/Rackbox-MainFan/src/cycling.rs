@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::config::Problems;
+
+/// `[cycling]` table: limits on how often an on/off fan may switch, so a
+/// narrow threshold band doesn't wear out the relay. All limits are off by
+/// default.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CyclingConfig {
+    /// Shortest time the fan stays on before it may be switched off.
+    pub min_on_secs: Option<u64>,
+    /// Shortest time the fan stays off before it may be switched on.
+    pub min_off_secs: Option<u64>,
+    /// Switches allowed in any hour; 0 means no limit.
+    pub max_switches_per_hour: Option<u32>,
+    /// Temperature that switches the fan on regardless of the limits.
+    /// Defaults to 5°C above the zone's `temp_maxima`.
+    pub critical_temp: Option<f32>,
+}
+
+impl CyclingConfig {
+    pub fn min_on_secs(&self) -> u64 {
+        self.min_on_secs.unwrap_or(0)
+    }
+
+    pub fn min_off_secs(&self) -> u64 {
+        self.min_off_secs.unwrap_or(0)
+    }
+
+    pub fn max_switches_per_hour(&self) -> u32 {
+        self.max_switches_per_hour.unwrap_or(0)
+    }
+
+    pub fn critical_temp(&self, temp_maxima: f32) -> f32 {
+        self.critical_temp.unwrap_or(temp_maxima + 5.0)
+    }
+
+    /// `critical_temp` stays unset, its default follows the zone's thresholds.
+    pub fn resolved(&self) -> Self {
+        CyclingConfig {
+            min_on_secs: Some(self.min_on_secs()),
+            min_off_secs: Some(self.min_off_secs()),
+            max_switches_per_hour: Some(self.max_switches_per_hour()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.critical_temp.is_some_and(|t| !t.is_finite()) {
+            problems.push(format_args!("{}.critical_temp", path), "must be a finite number");
+        }
+        if self.min_on_secs().max(self.min_off_secs()) >= 3600 {
+            problems.push(path, "min_on_secs and min_off_secs must be under an hour");
+        }
+    }
+}
+
+/// Why a switch was held back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Suppression {
+    MinOn { left: f32 },
+    MinOff { left: f32 },
+    RateLimit { switches: u32 },
+}
+
+impl Suppression {
+    /// Short name used as the metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Suppression::MinOn { .. } => "min_on",
+            Suppression::MinOff { .. } => "min_off",
+            Suppression::RateLimit { .. } => "rate_limit",
+        }
+    }
+}
+
+impl fmt::Display for Suppression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Suppression::MinOn { left } => write!(f, "minimum on time, {:.0}s left", left),
+            Suppression::MinOff { left } => write!(f, "minimum off time, {:.0}s left", left),
+            Suppression::RateLimit { switches } => {
+                write!(f, "{} switches in the last hour", switches)
+            }
+        }
+    }
+}
+
+/// Switch history of one fan, driven by the control cycle's `dt`.
+#[derive(Debug, Default)]
+pub struct CycleGuard {
+    /// Seconds since the guard started.
+    clock: f32,
+    /// Fan state seen on the last tick and when it last changed.
+    fan_on: Option<bool>,
+    changed_at: Option<f32>,
+    /// Times of the switches in the last hour.
+    switches: VecDeque<f32>,
+}
+
+impl CycleGuard {
+    /// Advances the clock and notes the current fan state, so switches made
+    /// by fail-safe or overrides count too.
+    pub fn tick(&mut self, dt: f32, fan_on: bool) {
+        self.clock += dt;
+        self.observe(fan_on);
+        while self.switches.front().is_some_and(|&t| self.clock - t >= 3600.0) {
+            self.switches.pop_front();
+        }
+    }
+
+    /// Records the state after a switch made within the current cycle.
+    pub fn observe(&mut self, fan_on: bool) {
+        if let Some(previous) = self.fan_on
+            && previous != fan_on
+        {
+            self.changed_at = Some(self.clock);
+            self.switches.push_back(self.clock);
+        }
+        self.fan_on = Some(fan_on);
+    }
+
+    /// Whether the fan may be switched to `on` now.
+    pub fn check(&self, config: &CyclingConfig, on: bool) -> Result<(), Suppression> {
+        if let Some(changed_at) = self.changed_at {
+            let held = self.clock - changed_at;
+            let (min_on, min_off) = (config.min_on_secs() as f32, config.min_off_secs() as f32);
+            if on && held < min_off {
+                return Err(Suppression::MinOff { left: min_off - held });
+            }
+            if !on && held < min_on {
+                return Err(Suppression::MinOn { left: min_on - held });
+            }
+        }
+
+        let max = config.max_switches_per_hour();
+        let switches = self.switches.len() as u32;
+        if max > 0 && switches >= max {
+            return Err(Suppression::RateLimit { switches });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_minimum_times_and_hourly_rate() {
+        let config = CyclingConfig {
+            min_on_secs: Some(60),
+            min_off_secs: Some(30),
+            max_switches_per_hour: Some(3),
+            critical_temp: None,
+        };
+        let mut guard = CycleGuard::default();
+        guard.tick(0.0, false);
+        assert_eq!(guard.check(&config, true), Ok(()));
+
+        guard.observe(true);
+        guard.tick(20.0, true);
+        assert_eq!(guard.check(&config, false), Err(Suppression::MinOn { left: 40.0 }));
+        guard.tick(40.0, true);
+        assert_eq!(guard.check(&config, false), Ok(()));
+
+        guard.observe(false);
+        guard.tick(10.0, false);
+        assert_eq!(guard.check(&config, true), Err(Suppression::MinOff { left: 20.0 }));
+        guard.tick(20.0, true);
+        guard.tick(60.0, true);
+
+        // Three switches so far: the fourth waits until the first is an hour old.
+        assert_eq!(guard.check(&config, false), Err(Suppression::RateLimit { switches: 3 }));
+        guard.tick(3600.0 - 150.0, true);
+        assert_eq!(guard.check(&config, false), Ok(()));
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::Problems;
+
+/// `[failsafe]` table: how a zone reacts when its control temperature can't
+/// be read.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FailsafeConfig {
+    /// Consecutive failed cycles before the fan is forced on. Until then the
+    /// fan keeps its last state.
+    pub failures: Option<u32>,
+    /// How long readings may be missing before the sensor is reported lost;
+    /// 0 disables the report.
+    pub lost_after_secs: Option<u64>,
+    /// Shell command run once when the sensor is reported lost.
+    pub lost_command: Option<String>,
+}
+
+impl FailsafeConfig {
+    pub fn failures(&self) -> u32 {
+        self.failures.unwrap_or(1)
+    }
+
+    pub fn lost_after(&self) -> Duration {
+        Duration::from_secs(self.lost_after_secs.unwrap_or(300))
+    }
+
+    pub fn resolved(&self) -> Self {
+        FailsafeConfig {
+            failures: Some(self.failures()),
+            lost_after_secs: Some(self.lost_after().as_secs()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.failures == Some(0) {
+            problems.push(format_args!("{}.failures", path), "must be at least 1");
+        }
+        if let Some(command) = &self.lost_command
+            && command.trim().is_empty()
+        {
+            problems.push(format_args!("{}.lost_command", path), "must not be empty");
+        }
+    }
+}
+
+/// Sensor health of a zone, driven by consecutive failed cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Ok,
+    /// Readings failing; the fan holds its last state.
+    Degraded { failures: u32 },
+    /// Too many failures; the fan is forced fully on.
+    FailSafe { failures: u32 },
+}
+
+impl Health {
+    pub fn failures(&self) -> u32 {
+        match self {
+            Health::Ok => 0,
+            Health::Degraded { failures } | Health::FailSafe { failures } => *failures,
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Ok => write!(f, "ok"),
+            Health::Degraded { failures } => write!(f, "degraded ({} failed reads)", failures),
+            Health::FailSafe { failures } => write!(f, "fail-safe ({} failed reads)", failures),
+        }
+    }
+}
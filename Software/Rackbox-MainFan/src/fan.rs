@@ -0,0 +1,331 @@
+use anyhow::Result;
+use log::{error, info};
+use rppal::gpio::{Gpio, OutputPin};
+use rppal::pwm::{Channel, Polarity, Pwm};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::Problems;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FanMode {
+    /// Drive the fan pin fully high or low between `temp_minima` and `temp_maxima`.
+    #[default]
+    OnOff,
+    /// Drive the fan with a duty cycle taken from the `[pwm]` curve.
+    Pwm,
+    /// Closed-loop regulation towards the `[pid]` setpoint on a PWM output.
+    Pid,
+}
+
+impl fmt::Display for FanMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanMode::OnOff => write!(f, "onoff"),
+            FanMode::Pwm => write!(f, "pwm"),
+            FanMode::Pid => write!(f, "pid"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CurvePoint {
+    pub temp: f32,
+    pub duty: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PwmConfig {
+    /// Hardware PWM channel (0-3). Software PWM on `fan_gpio` when unset.
+    pub channel: Option<u8>,
+    pub frequency_hz: Option<f64>,
+    pub min_duty: Option<f32>,
+    #[serde(default)]
+    pub curve: Vec<CurvePoint>,
+}
+
+impl PwmConfig {
+    /// Software PWM on `fan_gpio` with default frequency and no curve.
+    pub fn software() -> Self {
+        PwmConfig {
+            channel: None,
+            frequency_hz: None,
+            min_duty: None,
+            curve: Vec::new(),
+        }
+    }
+
+    pub fn frequency(&self) -> f64 {
+        match self.frequency_hz {
+            Some(freq) => freq,
+            // Intel 4-pin fans expect 25 kHz; software PWM can't get near that.
+            None if self.channel.is_some() => 25_000.0,
+            None => 100.0,
+        }
+    }
+
+    /// `channel` stays unset for software PWM.
+    pub fn resolved(&self) -> Self {
+        PwmConfig {
+            frequency_hz: Some(self.frequency()),
+            min_duty: Some(self.min_duty.unwrap_or(0.0)),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if let Some(channel) = self.channel
+            && channel > 3
+        {
+            problems.push(format_args!("{}.channel", path),
+                          format_args!("hardware PWM channel {} does not exist (0-3)", channel));
+        }
+        if let Some(freq) = self.frequency_hz
+            && !(freq.is_finite() && freq > 0.0)
+        {
+            problems.push(format_args!("{}.frequency_hz", path), "must be greater than 0");
+        }
+        if let Some(min_duty) = self.min_duty
+            && !(0.0..=100.0).contains(&min_duty)
+        {
+            problems.push(format_args!("{}.min_duty", path),
+                          format_args!("must be between 0 and 100 (got {})", min_duty));
+        }
+        for (i, point) in self.curve.iter().enumerate() {
+            if !point.temp.is_finite() {
+                problems.push(format_args!("{}.curve[{}].temp", path, i), "must be a finite number");
+            }
+            if !(0.0..=100.0).contains(&point.duty) {
+                problems.push(format_args!("{}.curve[{}].duty", path, i),
+                              format_args!("must be between 0 and 100 (got {})", point.duty));
+            }
+            if i > 0 && self.curve[i - 1].temp == point.temp {
+                problems.push(format_args!("{}.curve", path),
+                              format_args!("two points at {}°C", point.temp));
+            }
+        }
+    }
+
+    /// Duty cycle (0-100%) for a temperature, linearly interpolated between
+    /// curve points. Below the first point the fan is off; any non-zero duty
+    /// is raised to `min_duty` so the fan doesn't stall.
+    pub fn duty_for(&self, temp: f32) -> f32 {
+        let (first, last) = match (self.curve.first(), self.curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 100.0,
+        };
+
+        if temp < first.temp {
+            return 0.0;
+        }
+
+        let duty = if temp >= last.temp {
+            last.duty
+        } else {
+            self.curve
+                .windows(2)
+                .find(|w| temp >= w[0].temp && temp < w[1].temp)
+                .map(|w| {
+                    let span = w[1].temp - w[0].temp;
+                    w[0].duty + (w[1].duty - w[0].duty) * (temp - w[0].temp) / span
+                })
+                .unwrap_or(last.duty)
+        };
+
+        self.clamp_duty(duty)
+    }
+
+    /// Keeps a non-zero duty between `min_duty` and 100%.
+    pub fn clamp_duty(&self, duty: f32) -> f32 {
+        if duty <= 0.0 {
+            0.0
+        } else {
+            duty.max(self.min_duty.unwrap_or(0.0)).min(100.0)
+        }
+    }
+}
+
+/// Hardware (or simulated) output behind a [`FanController`].
+pub trait FanDriver: Send {
+    /// Whether intermediate duty cycles are possible; otherwise any non-zero
+    /// duty means fully on.
+    fn proportional(&self) -> bool;
+
+    /// Drives the output at `duty` percent (0-100).
+    fn apply(&mut self, duty: f32) -> Result<()>;
+}
+
+/// Fan switched fully on/off through a GPIO (relay or MOSFET).
+struct SwitchPin(OutputPin);
+
+impl FanDriver for SwitchPin {
+    fn proportional(&self) -> bool {
+        false
+    }
+
+    fn apply(&mut self, duty: f32) -> Result<()> {
+        if duty > 0.0 {
+            self.0.set_high();
+        } else {
+            self.0.set_low();
+        }
+        Ok(())
+    }
+}
+
+/// rppal software PWM on a GPIO.
+struct SoftPwm {
+    pin: OutputPin,
+    frequency: f64,
+}
+
+impl FanDriver for SoftPwm {
+    fn proportional(&self) -> bool {
+        true
+    }
+
+    fn apply(&mut self, duty: f32) -> Result<()> {
+        if duty <= 0.0 {
+            self.pin.clear_pwm()?;
+            self.pin.set_low();
+        } else {
+            self.pin.set_pwm_frequency(self.frequency, f64::from(duty) / 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hardware PWM channel.
+struct HardPwm(Pwm);
+
+impl FanDriver for HardPwm {
+    fn proportional(&self) -> bool {
+        true
+    }
+
+    fn apply(&mut self, duty: f32) -> Result<()> {
+        self.0.set_duty_cycle(f64::from(duty) / 100.0)?;
+        Ok(())
+    }
+}
+
+/// Tracks the commanded fan state on top of a [`FanDriver`] and logs changes.
+pub struct FanController {
+    driver: Box<dyn FanDriver>,
+    label: String,
+    current_state: bool,
+    duty: f32,
+    /// The last write to the driver failed; the output state is unknown.
+    fault: bool,
+    /// On/off transitions since start.
+    switches: u64,
+}
+
+impl FanController {
+    pub fn new(gpio_num: u8) -> Result<Self> {
+        let gpio = Gpio::new()?;
+        let mut pin = gpio.get(gpio_num)?.into_output();
+        pin.set_low();
+
+        info!("Fan controller on GPIO {}", gpio_num);
+        Ok(Self::with_driver(format!("GPIO {}", gpio_num), Box::new(SwitchPin(pin))))
+    }
+
+    pub fn new_pwm(gpio_num: u8, pwm: &PwmConfig) -> Result<Self> {
+        let frequency = pwm.frequency();
+
+        let controller = match pwm.channel {
+            Some(channel) => {
+                let channel = Channel::try_from(channel)?;
+                let pwm = Pwm::with_frequency(channel, frequency, 0.0, Polarity::Normal, true)?;
+                info!("Fan controller on hardware PWM {} ({} Hz)", channel, frequency);
+                Self::with_driver(format!("PWM {}", channel), Box::new(HardPwm(pwm)))
+            }
+            None => {
+                let gpio = Gpio::new()?;
+                let mut pin = gpio.get(gpio_num)?.into_output();
+                pin.set_low();
+                info!("Fan controller on GPIO {} (software PWM, {} Hz)", gpio_num, frequency);
+                Self::with_driver(format!("GPIO {}", gpio_num),
+                                  Box::new(SoftPwm { pin, frequency }))
+            }
+        };
+
+        Ok(controller)
+    }
+
+    /// Controller for an output that starts switched off.
+    pub fn with_driver(label: String, driver: Box<dyn FanDriver>) -> Self {
+        FanController {
+            driver,
+            label,
+            current_state: false,
+            duty: 0.0,
+            fault: false,
+            switches: 0,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.current_state
+    }
+
+    pub fn duty(&self) -> f32 {
+        self.duty
+    }
+
+    pub fn switches(&self) -> u64 {
+        self.switches
+    }
+
+    pub fn is_faulty(&self) -> bool {
+        self.fault
+    }
+
+    pub fn turn_on(&mut self) {
+        self.set_duty(100.0);
+    }
+
+    pub fn turn_off(&mut self) {
+        self.set_duty(0.0);
+    }
+
+    /// Sets the fan duty cycle in percent. On/off outputs treat any non-zero
+    /// duty as fully on.
+    pub fn set_duty(&mut self, duty: f32) {
+        let mut duty = duty.clamp(0.0, 100.0);
+        if !self.driver.proportional() && duty > 0.0 {
+            duty = 100.0;
+        }
+        if (duty - self.duty).abs() < f32::EPSILON && !self.fault {
+            return;
+        }
+
+        if let Err(e) = self.driver.apply(duty) {
+            error!("Failed to set fan duty on {}: {}", self.label, e);
+            self.fault = true;
+            return;
+        }
+        self.fault = false;
+
+        let on = duty > 0.0;
+        if !self.driver.proportional() {
+            if on {
+                info!("Fan turned ON ({})", self.label);
+            } else {
+                info!("Fan turned OFF ({})", self.label);
+            }
+        } else if duty.round() != self.duty.round() {
+            // Only log steps of a whole percent to keep syslog readable.
+            info!("Fan duty {:.0}% -> {:.0}% ({})", self.duty, duty, self.label);
+        }
+        if on != self.current_state {
+            self.switches += 1;
+        }
+        self.duty = duty;
+        self.current_state = on;
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::config::Problems;
+use crate::sensor::{Environment, Sensor};
+
+/// Values a DS18B20 reports instead of a temperature: 85°C is the power-on
+/// reset value (read before a conversion finished), -127°C a lost device.
+const DEFAULT_REJECT_VALUES: [f32; 2] = [85.0, -127.0];
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Smoothing {
+    /// Readings go to the control decision as read.
+    #[default]
+    None,
+    /// Mean of the last `window` accepted readings.
+    Average,
+    /// Median of the last `window` accepted readings; ignores single spikes.
+    Median,
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Smoothing::None => write!(f, "none"),
+            Smoothing::Average => write!(f, "average"),
+            Smoothing::Median => write!(f, "median"),
+        }
+    }
+}
+
+/// `[filter]` table: sanity checks applied to every sensor before its
+/// reading reaches the zones.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Exact readings treated as sensor errors, on sensors that report
+    /// failures that way (1-Wire probes).
+    pub reject_values: Option<Vec<f32>>,
+    /// Largest plausible change in °C between two consecutive readings.
+    pub max_jump: Option<f32>,
+    /// Extra reads within one cycle after a rejected reading.
+    pub retries: Option<u32>,
+    pub smoothing: Option<Smoothing>,
+    /// Readings the smoothing filter works over.
+    pub window: Option<usize>,
+}
+
+impl FilterConfig {
+    pub fn reject_values(&self) -> &[f32] {
+        self.reject_values.as_deref().unwrap_or(&DEFAULT_REJECT_VALUES)
+    }
+
+    pub fn max_jump(&self) -> f32 {
+        self.max_jump.unwrap_or(10.0)
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(2)
+    }
+
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing.unwrap_or_default()
+    }
+
+    pub fn window(&self) -> usize {
+        match self.smoothing() {
+            Smoothing::None => 1,
+            _ => self.window.unwrap_or(5),
+        }
+    }
+
+    /// `window` is only set with smoothing on; validation refuses it otherwise.
+    pub fn resolved(&self) -> Self {
+        FilterConfig {
+            reject_values: Some(self.reject_values().to_vec()),
+            max_jump: Some(self.max_jump()),
+            retries: Some(self.retries()),
+            smoothing: Some(self.smoothing()),
+            window: (self.smoothing() != Smoothing::None).then(|| self.window()),
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if let Some(max_jump) = self.max_jump
+            && !(max_jump.is_finite() && max_jump > 0.0)
+        {
+            problems.push(format_args!("{}.max_jump", path), "must be greater than 0");
+        }
+        if self.retries.is_some_and(|r| r > 10) {
+            problems.push(format_args!("{}.retries", path),
+                          "at most 10 (each DS18B20 read takes up to 750 ms)");
+        }
+        if let Some(window) = self.window {
+            if !(1..=60).contains(&window) {
+                problems.push(format_args!("{}.window", path), "must be between 1 and 60");
+            } else if self.smoothing() == Smoothing::None {
+                problems.push(format_args!("{}.window", path),
+                              "has no effect without smoothing = \"average\" or \"median\"");
+            }
+        }
+    }
+}
+
+/// Wraps a sensor with the `[filter]` checks: error codes and readings outside
+/// the range of the sensor's chip, where it has them, and implausibly sudden
+/// readings are rejected and re-read, and accepted readings are smoothed.
+pub struct FilteredSensor {
+    inner: Box<dyn Sensor>,
+    config: FilterConfig,
+    history: VecDeque<f32>,
+}
+
+impl FilteredSensor {
+    pub fn new(inner: Box<dyn Sensor>, config: FilterConfig) -> Self {
+        FilteredSensor {
+            inner,
+            config,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Why a raw reading can't be trusted, if it can't.
+    fn check(&self, temp: f32) -> Option<Rejection> {
+        if self.inner.has_error_codes() && self.config.reject_values().contains(&temp) {
+            return Some(Rejection::Invalid(format!("{}°C is a sensor error code", temp)));
+        }
+        if let Some((low, high)) = self.inner.range()
+            && !(low..=high).contains(&temp)
+        {
+            return Some(Rejection::Invalid(format!("{}°C is outside the sensor range", temp)));
+        }
+        if let Some(&last) = self.history.back()
+            && (temp - last).abs() > self.config.max_jump()
+        {
+            return Some(Rejection::Jump { from: last, to: temp });
+        }
+        None
+    }
+
+    fn smoothed(&self) -> f32 {
+        let mut values: Vec<f32> = self.history.iter().copied().collect();
+        match self.config.smoothing() {
+            Smoothing::None => values[values.len() - 1],
+            Smoothing::Average => values.iter().sum::<f32>() / values.len() as f32,
+            Smoothing::Median => {
+                values.sort_by(f32::total_cmp);
+                let mid = values.len() / 2;
+                if values.len().is_multiple_of(2) {
+                    (values[mid - 1] + values[mid]) / 2.0
+                } else {
+                    values[mid]
+                }
+            }
+        }
+    }
+}
+
+impl Sensor for FilteredSensor {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn environment(&self) -> Environment {
+        self.inner.environment()
+    }
+
+    fn range(&self) -> Option<(f32, f32)> {
+        self.inner.range()
+    }
+
+    fn has_error_codes(&self) -> bool {
+        self.inner.has_error_codes()
+    }
+
+    fn read_temperature(&mut self) -> Result<f32> {
+        let mut attempts = 0;
+        let mut only_jumps = true;
+
+        let rejection = loop {
+            let rejection = match self.inner.read_temperature() {
+                Ok(temp) => match self.check(temp) {
+                    None => {
+                        if self.history.len() >= self.config.window() {
+                            self.history.pop_front();
+                        }
+                        self.history.push_back(temp);
+                        return Ok(self.smoothed());
+                    }
+                    Some(rejection) => rejection,
+                },
+                Err(e) => Rejection::Invalid(format!("{:#}", e)),
+            };
+            warn!("Sensor '{}': rejected reading ({})", self.name(), rejection);
+            only_jumps &= matches!(rejection, Rejection::Jump { .. });
+
+            if attempts == self.config.retries() {
+                break rejection;
+            }
+            attempts += 1;
+        };
+
+        // A jump that persists through every retry is a real change, not a
+        // glitch: start over from the next reading instead of rejecting forever.
+        if only_jumps {
+            self.history.clear();
+        }
+        Err(anyhow::anyhow!("All {} readings rejected, last: {}", attempts + 1, rejection))
+    }
+}
+
+enum Rejection {
+    Invalid(String),
+    Jump { from: f32, to: f32 },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Invalid(reason) => write!(f, "{}", reason),
+            Rejection::Jump { from, to } => write!(f, "jump from {:.1}°C to {:.1}°C", from, to),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{ScriptedSensor, SensorScript};
+
+    fn sensor(config: FilterConfig) -> (FilteredSensor, SensorScript) {
+        let (sensor, script) = ScriptedSensor::new("probe");
+        (FilteredSensor::new(Box::new(sensor), config), script)
+    }
+
+    #[test]
+    fn error_codes_are_retried() {
+        let (mut sensor, script) = sensor(FilterConfig::default());
+        script.push(85.0);
+        script.push(24.5);
+        assert_eq!(sensor.read_temperature().unwrap(), 24.5);
+
+        script.push(-127.0);
+        script.push(-127.0);
+        script.push(-127.0);
+        assert!(sensor.read_temperature().is_err());
+    }
+
+    /// A sensor with neither error codes nor a known range.
+    struct Plain(ScriptedSensor);
+
+    impl Sensor for Plain {
+        fn name(&self) -> &str {
+            self.0.name()
+        }
+
+        fn read_temperature(&mut self) -> Result<f32> {
+            self.0.read_temperature()
+        }
+    }
+
+    #[test]
+    fn error_codes_and_range_only_apply_to_sensors_that_have_them() {
+        let (inner, script) = ScriptedSensor::new("plain");
+        let config = FilterConfig {
+            retries: Some(0),
+            max_jump: Some(500.0),
+            ..FilterConfig::default()
+        };
+        let mut sensor = FilteredSensor::new(Box::new(Plain(inner)), config);
+        for temp in [85.0, -127.0, 130.0] {
+            script.push(temp);
+            assert_eq!(sensor.read_temperature().unwrap(), temp);
+        }
+    }
+
+    #[test]
+    fn implausible_jump_is_rejected_until_it_persists() {
+        let config = FilterConfig { retries: Some(0), ..FilterConfig::default() };
+        let (mut sensor, script) = sensor(config);
+        script.push(25.0);
+        assert_eq!(sensor.read_temperature().unwrap(), 25.0);
+
+        script.push(60.0);
+        assert!(sensor.read_temperature().is_err());
+        // Still 60 on the next cycle: accepted as the new baseline.
+        assert_eq!(sensor.read_temperature().unwrap(), 60.0);
+    }
+
+    #[test]
+    fn median_ignores_a_single_spike() {
+        let config = FilterConfig {
+            smoothing: Some(Smoothing::Median),
+            window: Some(3),
+            ..FilterConfig::default()
+        };
+        let (mut sensor, script) = sensor(config);
+        for temp in [25.0, 25.5, 33.0, 25.2] {
+            script.push(temp);
+        }
+        let smoothed: Vec<f32> = (0..4).map(|_| sensor.read_temperature().unwrap()).collect();
+        assert_eq!(smoothed, vec![25.0, 25.25, 25.5, 25.5]);
+    }
+
+    #[test]
+    fn average_over_window() {
+        let config = FilterConfig {
+            smoothing: Some(Smoothing::Average),
+            window: Some(2),
+            ..FilterConfig::default()
+        };
+        let (mut sensor, script) = sensor(config);
+        for temp in [20.0, 22.0, 24.0] {
+            script.push(temp);
+        }
+        let smoothed: Vec<f32> = (0..3).map(|_| sensor.read_temperature().unwrap()).collect();
+        assert_eq!(smoothed, vec![20.0, 21.0, 23.0]);
+    }
+}
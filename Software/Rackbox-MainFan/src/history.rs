@@ -0,0 +1,552 @@
+//! Temperature and fan history: an append-only file of JSON lines with every
+//! sensor reading of each cycle and every fan on/off transition, pruned to
+//! the configured retention. `rackfan_daemon history` reads it back.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, Problems};
+use crate::control::SensorStatus;
+use crate::zone::ZoneStatus;
+use crate::DEFAULT_CONFIG_PATH;
+
+const DEFAULT_PATH: &str = "/var/lib/rackfan/history.jsonl";
+
+/// Seconds between prunes while running; one also happens at startup.
+const PRUNE_EVERY_SECS: u64 = 24 * 3600;
+
+const USAGE: &str = "\
+usage: rackfan_daemon history [--config <path>] [--file <path>] [--since <time>]
+                              [--until <time>] [--events] [--csv [--output <csv>]]
+
+<time> is an epoch timestamp or a time ago: 90m, 24h, 7d (default --since 24h).
+--csv exports the readings in the format read by `rackfan_daemon replay`.";
+
+/// `[history]` table; history is only kept when the table is present.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub path: Option<String>,
+    pub retention_days: Option<u32>,
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_PATH)
+    }
+
+    pub fn retention_days(&self) -> u32 {
+        self.retention_days.unwrap_or(7)
+    }
+
+    pub fn resolved(&self) -> Self {
+        HistoryConfig {
+            path: Some(self.path().to_string()),
+            retention_days: Some(self.retention_days()),
+        }
+    }
+
+    fn retention_secs(&self) -> u64 {
+        u64::from(self.retention_days()) * 24 * 3600
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.path.as_deref().is_some_and(|p| !p.starts_with('/')) {
+            problems.push(format_args!("{}.path", path), "must be an absolute path");
+        }
+        if self.retention_days == Some(0) {
+            problems.push(format_args!("{}.retention_days", path), "must be at least 1");
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Record {
+    Reading {
+        time: u64,
+        sensor: String,
+        /// None for a failed read.
+        temperature: Option<f32>,
+    },
+    Fan {
+        time: u64,
+        zone: String,
+        on: bool,
+        duty: f32,
+        reason: Option<String>,
+    },
+}
+
+impl Record {
+    fn time(&self) -> u64 {
+        match self {
+            Record::Reading { time, .. } | Record::Fan { time, .. } => *time,
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Writer side, owned by the daemon.
+pub struct HistoryStore {
+    config: HistoryConfig,
+    file: File,
+    /// Fan state last written per zone, to record transitions only.
+    fans: HashMap<String, bool>,
+    last_prune: u64,
+    /// A write failed; warned once until writes succeed again.
+    failing: bool,
+}
+
+impl HistoryStore {
+    pub fn open(config: &HistoryConfig, now: u64) -> Result<Self> {
+        let path = config.path();
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        prune(path, now.saturating_sub(config.retention_secs()))?;
+        let file = append(path)?;
+
+        info!("Recording history to {} ({} days)", path, config.retention_days());
+        Ok(HistoryStore {
+            config: config.clone(),
+            file,
+            fans: HashMap::new(),
+            last_prune: now,
+            failing: false,
+        })
+    }
+
+    /// Records one cycle: every reading, plus the zones whose fan changed.
+    pub fn record(&mut self, now: u64, sensors: &[SensorStatus], zones: &[ZoneStatus]) {
+        let mut lines = String::new();
+        let mut records: Vec<Record> = sensors.iter()
+            .map(|sensor| Record::Reading {
+                time: now,
+                sensor: sensor.name.clone(),
+                temperature: sensor.temperature,
+            })
+            .collect();
+        for zone in zones {
+            if self.fans.insert(zone.name.clone(), zone.fan_on) != Some(zone.fan_on) {
+                records.push(Record::Fan {
+                    time: now,
+                    zone: zone.name.clone(),
+                    on: zone.fan_on,
+                    duty: zone.duty,
+                    reason: zone.last_reason.clone(),
+                });
+            }
+        }
+        for record in &records {
+            if let Ok(line) = serde_json::to_string(record) {
+                let _ = writeln!(lines, "{}", line);
+            }
+        }
+
+        let result = self.file.write_all(lines.as_bytes()).and_then(|()| self.file.flush());
+        match (result, self.failing) {
+            (Err(e), false) => {
+                warn!("History write to {} failed: {}", self.config.path(), e);
+                self.failing = true;
+            }
+            (Ok(()), true) => {
+                info!("History writes to {} recovered", self.config.path());
+                self.failing = false;
+            }
+            _ => {}
+        }
+
+        if now.saturating_sub(self.last_prune) >= PRUNE_EVERY_SECS {
+            self.last_prune = now;
+            let path = self.config.path();
+            match prune(path, now.saturating_sub(self.config.retention_secs()))
+                .and_then(|()| append(path))
+            {
+                Ok(file) => self.file = file,
+                Err(e) => warn!("History prune failed: {:#}", e),
+            }
+        }
+    }
+}
+
+fn append(path: &str) -> Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+        .with_context(|| format!("Failed to open history {}", path))
+}
+
+/// Rewrites the file without records older than `cutoff`. Lines that do not
+/// parse (a write cut short by a crash) are dropped too.
+fn prune(path: &str, cutoff: u64) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read history {}", path)),
+    };
+
+    let temp = format!("{}.tmp", path);
+    let mut out = BufWriter::new(File::create(&temp)
+        .with_context(|| format!("Failed to create {}", temp))?);
+    let (mut kept, mut dropped) = (0, 0);
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) if record.time() >= cutoff => {
+                writeln!(out, "{}", line)?;
+                kept += 1;
+            }
+            _ => dropped += 1,
+        }
+    }
+    out.flush()?;
+    drop(out);
+
+    if dropped > 0 {
+        fs::rename(&temp, path).with_context(|| format!("Failed to replace {}", path))?;
+        info!("History pruned: {} records dropped, {} kept", dropped, kept);
+    } else {
+        fs::remove_file(&temp)?;
+    }
+    Ok(())
+}
+
+/// Records within `[since, until]`, oldest first.
+fn read(path: &str, since: u64, until: u64) -> Result<Vec<Record>> {
+    let file = File::open(path).with_context(|| format!("Failed to read history {}", path))?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(record) = serde_json::from_str::<Record>(&line?)
+            && (since..=until).contains(&record.time())
+        {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Epoch seconds for `1760000000`, or a time ago like `90m`, `24h`, `7d`.
+fn parse_time(text: &str, now: u64) -> Result<u64> {
+    let invalid = || anyhow::anyhow!("invalid time '{}', use e.g. 90m, 24h, 7d or an epoch", text);
+    if let Ok(epoch) = text.parse::<u64>() {
+        return Ok(epoch);
+    }
+    let (number, unit) = text.split_at(text.len().saturating_sub(1));
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        "d" => number * 24 * 3600,
+        _ => return Err(invalid()),
+    };
+    Ok(now.saturating_sub(secs))
+}
+
+/// `2026-10-18 09:30:05Z`
+fn format_time(epoch: u64) -> String {
+    let (days, secs) = (epoch / 86400, epoch % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}Z",
+            year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+}
+
+#[derive(Default)]
+struct SensorSummary {
+    samples: u64,
+    errors: u64,
+    min: f32,
+    max: f32,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct ZoneSummary {
+    switches: u64,
+    on_secs: u64,
+}
+
+/// Min/avg/max per sensor, switches and time on per zone, and optionally
+/// every fan event.
+fn summarize(records: &[Record], since: u64, until: u64, events: bool) -> String {
+    let mut sensors: BTreeMap<&str, SensorSummary> = BTreeMap::new();
+    let mut zones: BTreeMap<&str, ZoneSummary> = BTreeMap::new();
+    // When each zone's fan went on; a zone whose first event is "off" was on
+    // since the start of the range.
+    let mut on_since: HashMap<&str, u64> = HashMap::new();
+    let mut out = String::new();
+    let _ = writeln!(out, "history {} .. {}", format_time(since), format_time(until));
+
+    let mut event_lines = String::new();
+    for record in records {
+        match record {
+            Record::Reading { sensor, temperature, .. } => {
+                let summary = sensors.entry(sensor).or_default();
+                match temperature {
+                    Some(temp) => {
+                        if summary.samples == 0 || *temp < summary.min {
+                            summary.min = *temp;
+                        }
+                        if summary.samples == 0 || *temp > summary.max {
+                            summary.max = *temp;
+                        }
+                        summary.samples += 1;
+                        summary.sum += f64::from(*temp);
+                    }
+                    None => summary.errors += 1,
+                }
+            }
+            Record::Fan { time, zone, on, duty, reason } => {
+                let first = !zones.contains_key(zone.as_str());
+                let summary = zones.entry(zone).or_default();
+                if *on {
+                    on_since.insert(zone, *time);
+                } else if let Some(start) = on_since.remove(zone.as_str()) {
+                    summary.on_secs += time.saturating_sub(start);
+                } else if first {
+                    summary.on_secs += time.saturating_sub(since);
+                }
+                if !first {
+                    summary.switches += 1;
+                }
+                let _ = writeln!(event_lines, "{}  {:<12} {:<3} {:>3.0}%  {}",
+                                 format_time(*time), zone, if *on { "on" } else { "off" },
+                                 duty, reason.as_deref().unwrap_or(""));
+            }
+        }
+    }
+    for (zone, start) in on_since {
+        if let Some(summary) = zones.get_mut(zone) {
+            summary.on_secs += until.saturating_sub(start);
+        }
+    }
+
+    let _ = writeln!(out, "\n{:<16} {:>8} {:>7} {:>7} {:>7} {:>7}",
+                     "sensor", "samples", "errors", "min", "avg", "max");
+    for (name, s) in &sensors {
+        if s.samples == 0 {
+            let _ = writeln!(out, "{:<16} {:>8} {:>7}", name, 0, s.errors);
+        } else {
+            let _ = writeln!(out, "{:<16} {:>8} {:>7} {:>7.1} {:>7.1} {:>7.1}",
+                             name, s.samples, s.errors, s.min,
+                             s.sum / s.samples as f64, s.max);
+        }
+    }
+
+    if !zones.is_empty() {
+        let _ = writeln!(out, "\n{:<16} {:>8} {:>8}", "zone", "switches", "on time");
+        for (name, z) in &zones {
+            let _ = writeln!(out, "{:<16} {:>8} {:>8}", name, z.switches,
+                             format_duration(z.on_secs));
+        }
+    }
+    if events && !event_lines.is_empty() {
+        let _ = write!(out, "\nfan events:\n{}", event_lines);
+    }
+    out
+}
+
+/// Readings as `timestamp,<sensor>...` rows, one per cycle.
+fn to_csv(records: &[Record]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    let mut rows: BTreeMap<u64, HashMap<&str, Option<f32>>> = BTreeMap::new();
+    for record in records {
+        if let Record::Reading { time, sensor, temperature } = record {
+            if !names.contains(&sensor.as_str()) {
+                names.push(sensor);
+            }
+            rows.entry(*time).or_default().insert(sensor, *temperature);
+        }
+    }
+
+    let mut out = format!("timestamp,{}\n", names.join(","));
+    for (time, row) in rows {
+        let cells: Vec<String> = names.iter()
+            .map(|name| match row.get(name) {
+                Some(Some(temp)) => format!("{:.2}", temp),
+                Some(None) => "error".to_string(),
+                None => String::new(),
+            })
+            .collect();
+        let _ = writeln!(out, "{},{}", time, cells.join(","));
+    }
+    out
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let mut config_path = None;
+    let mut file = None;
+    let mut since = None;
+    let mut until = None;
+    let mut events = false;
+    let mut csv = false;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().context(USAGE)?),
+            "--file" => file = Some(args.next().context(USAGE)?.clone()),
+            "--since" => since = Some(args.next().context(USAGE)?),
+            "--until" => until = Some(args.next().context(USAGE)?),
+            "--events" => events = true,
+            "--csv" => csv = true,
+            "--output" => output = Some(args.next().context(USAGE)?),
+            other => return Err(anyhow::anyhow!("unexpected argument '{}'\n{}", other, USAGE)),
+        }
+    }
+
+    let file = match file {
+        Some(file) => file,
+        None => {
+            let config = match config_path {
+                Some(path) => Config::load(path)?,
+                None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::load(DEFAULT_CONFIG_PATH)?,
+                None => Config::default(),
+            };
+            config.history.as_ref().map_or(DEFAULT_PATH, HistoryConfig::path).to_string()
+        }
+    };
+
+    let now = now();
+    let since = parse_time(since.map_or("24h", String::as_str), now)?;
+    let until = until.map_or(Ok(now), |t| parse_time(t, now))?;
+    let records = read(&file, since, until)?;
+
+    if csv {
+        let content = to_csv(&records);
+        match output {
+            Some(path) => {
+                fs::write(path, content).with_context(|| format!("Failed to write {}", path))?;
+                println!("Readings written to {}", path);
+            }
+            None => print!("{}", content),
+        }
+    } else {
+        print!("{}", summarize(&records, since, until, events));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmLevel;
+    use std::process;
+
+    fn sensor(name: &str, temperature: Option<f32>) -> SensorStatus {
+        SensorStatus {
+            name: name.to_string(),
+            temperature,
+            humidity: None,
+            pressure: None,
+            dew_point: None,
+            error: None,
+        }
+    }
+
+    fn zone(fan_on: bool) -> ZoneStatus {
+        ZoneStatus {
+            name: "top".to_string(),
+            fan_mode: "onoff".to_string(),
+            control: Default::default(),
+            control_remaining_secs: None,
+            temperature: None,
+            humidity: None,
+            humid: false,
+            temp_minima: 25.0,
+            temp_maxima: 30.0,
+            fan_on,
+            duty: if fan_on { 100.0 } else { 0.0 },
+            switches: 0,
+            rpm: None,
+            health: "ok".to_string(),
+            failsafe_activations: 0,
+            alarm: AlarmLevel::Normal,
+            profile: None,
+            profile_suspended: false,
+            suppressed_switches: Default::default(),
+            last_reason: None,
+        }
+    }
+
+    #[test]
+    fn records_cycles_and_transitions_and_prunes_old_ones() {
+        let path = std::env::temp_dir().join(format!("rackfan-history-{}.jsonl", process::id()));
+        let config = HistoryConfig {
+            path: Some(path.display().to_string()),
+            retention_days: Some(1),
+        };
+        let day = 24 * 3600;
+
+        let mut store = HistoryStore::open(&config, 1000).unwrap();
+        store.record(1000, &[sensor("inlet", Some(24.0))], &[zone(false)]);
+        store.record(1010, &[sensor("inlet", Some(31.0))], &[zone(true)]);
+        store.record(1020, &[sensor("inlet", None)], &[zone(true)]);
+        store.record(1630, &[sensor("inlet", Some(26.0))], &[zone(false)]);
+
+        let records = read(path.to_str().unwrap(), 0, u64::MAX).unwrap();
+        assert_eq!(records.len(), 7);
+        let summary = summarize(&records, 1000, 2000, true);
+        assert!(summary.contains("inlet                   3       1    24.0    27.0    31.0"),
+                "{}", summary);
+        assert!(summary.contains("top                     2    0h10m"), "{}", summary);
+
+        // Reopening a day later drops the first cycle.
+        drop(store);
+        HistoryStore::open(&config, 1005 + day).unwrap();
+        let records = read(path.to_str().unwrap(), 0, u64::MAX).unwrap();
+        assert_eq!(records.first().map(Record::time), Some(1010));
+
+        assert_eq!(to_csv(&records), "timestamp,inlet\n1010,31.00\n1020,error\n1630,26.00\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clock_stepping_back_does_not_add_on_time() {
+        let fan = |time, on| Record::Fan {
+            time,
+            zone: "top".to_string(),
+            on,
+            duty: if on { 100.0 } else { 0.0 },
+            reason: None,
+        };
+        // fake-hwclock restores a time ahead of the real one; NTP then steps
+        // the clock back while the fan is on.
+        let records = [fan(1500, true), fan(1400, false), fan(1410, true), fan(1470, false)];
+        let summary = summarize(&records, 1000, 2000, false);
+        assert!(summary.contains("top                     3    0h01m"), "{}", summary);
+    }
+
+    #[test]
+    fn times_are_epochs_or_ago() {
+        assert_eq!(parse_time("1760000000", 0).unwrap(), 1_760_000_000);
+        assert_eq!(parse_time("2h", 10_000).unwrap(), 2800);
+        assert_eq!(parse_time("7d", 10 * 86400).unwrap(), 3 * 86400);
+        assert!(parse_time("yesterday", 0).is_err());
+        assert_eq!(format_time(1_760_000_000), "2025-10-09 08:53:20Z");
+    }
+}
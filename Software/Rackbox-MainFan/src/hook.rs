@@ -0,0 +1,143 @@
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::Problems;
+
+/// `[hooks]` table: shell commands run on daemon events, so other tooling
+/// can react without changes to the daemon. Details are passed in
+/// `RACKFAN_*` environment variables.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Run when a zone's fan switches on, whatever the reason.
+    pub on_fan_on: Option<String>,
+    pub on_fan_off: Option<String>,
+    /// Run when a sensor starts failing; once per run of failed reads.
+    pub on_sensor_error: Option<String>,
+    /// Run when a zone enters fail-safe.
+    pub on_failsafe: Option<String>,
+    /// Time a hook may run before it is killed.
+    pub timeout_secs: Option<u64>,
+}
+
+impl HooksConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(30))
+    }
+
+    pub fn resolved(&self) -> Self {
+        HooksConfig { timeout_secs: Some(self.timeout().as_secs()), ..self.clone() }
+    }
+
+    fn commands(&self) -> [(&'static str, &Option<String>); 4] {
+        [
+            ("on_fan_on", &self.on_fan_on),
+            ("on_fan_off", &self.on_fan_off),
+            ("on_sensor_error", &self.on_sensor_error),
+            ("on_failsafe", &self.on_failsafe),
+        ]
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        for (event, command) in self.commands() {
+            if command.as_ref().is_some_and(|c| c.trim().is_empty()) {
+                problems.push(format_args!("{}.{}", path, event), "must not be empty");
+            }
+        }
+        if self.timeout_secs == Some(0) {
+            problems.push(format_args!("{}.timeout_secs", path), "must be at least 1");
+        }
+    }
+
+
+    /// Runs the hook of `event`, if configured.
+    pub fn run(&self, event: &str, env: &[(&str, String)]) {
+        let command = self.commands().into_iter()
+            .find(|(name, _)| *name == event)
+            .and_then(|(_, command)| command.as_ref());
+        if let Some(command) = command {
+            let mut env = env.to_vec();
+            env.push(("RACKFAN_EVENT", event.to_string()));
+            spawn(format!("Hook {}", event), command, &env, Some(self.timeout()));
+        }
+    }
+}
+
+/// Runs an alarm command through `sh -c` with `env` set, without blocking the
+/// control loop; its exit status is logged.
+pub fn run_command(command: &str, env: &[(&str, String)]) {
+    spawn("Alarm command".to_string(), command, env, None);
+}
+
+/// Starts `command` and a thread that waits for it, killing it and anything
+/// it started once `timeout` has passed. Returns the waiting thread.
+fn spawn(label: String, command: &str, env: &[(&str, String)], timeout: Option<Duration>)
+         -> Option<JoinHandle<()>> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        // Own process group, so a timeout also kills the command's children.
+        .process_group(0)
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            let command = command.to_string();
+            let deadline = timeout.map(|timeout| (Instant::now() + timeout, timeout));
+            Some(thread::spawn(move || {
+                let status = loop {
+                    match (child.try_wait(), deadline) {
+                        (Ok(Some(status)), _) => break Ok(status),
+                        (Ok(None), Some((deadline, timeout))) if Instant::now() >= deadline => {
+                            warn!("{} '{}' still running after {}s, killed",
+                                  label, command, timeout.as_secs_f32());
+                            // SAFETY: plain kill(2) of the group led by our child.
+                            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                            break child.wait();
+                        }
+                        (Ok(None), Some(_)) => thread::sleep(Duration::from_millis(50)),
+                        (Ok(None), None) => break child.wait(),
+                        (Err(e), _) => break Err(e),
+                    }
+                };
+                match status {
+                    Ok(status) if status.success() => info!("{} '{}' finished", label, command),
+                    Ok(status) => warn!("{} '{}' exited with {}", label, command, status),
+                    Err(e) => warn!("{} '{}': {}", label, command, e),
+                }
+            }))
+        }
+        Err(e) => {
+            error!("Failed to run {} '{}': {}", label.to_lowercase(), command, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn hooks_get_their_environment_and_are_killed_after_the_timeout() {
+        let path = std::env::temp_dir().join(format!("rackfan-hook-{}", process::id()));
+        let command = format!("echo \"$RACKFAN_STATE\" > {}; sleep 5", path.display());
+
+        let started = Instant::now();
+        let waiter = spawn("Hook on_fan_on".to_string(), &command,
+                           &[("RACKFAN_STATE", "on".to_string())],
+                           Some(Duration::from_millis(300)));
+        waiter.unwrap().join().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "on\n");
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Problems;
+
+/// `[humidity]` table: runs the fan while the relative humidity of a zone's
+/// sensors is high, alongside the temperature thresholds. Off unless `max` is
+/// set.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HumidityConfig {
+    /// Relative humidity (%) above which the fan runs.
+    pub max: Option<f32>,
+    /// Points below `max` the humidity must drop before the fan may stop.
+    pub hysteresis: Option<f32>,
+    /// Lowest duty while humid in the pwm and pid modes.
+    pub duty: Option<f32>,
+}
+
+impl HumidityConfig {
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis.unwrap_or(5.0)
+    }
+
+    pub fn duty(&self) -> f32 {
+        self.duty.unwrap_or(100.0)
+    }
+
+    pub fn resolved(&self) -> Self {
+        HumidityConfig {
+            hysteresis: Some(self.hysteresis()),
+            duty: Some(self.duty()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.max.is_some_and(|max| !(0.0..=100.0).contains(&max)) {
+            problems.push(format_args!("{}.max", path), "must be between 0 and 100");
+        }
+        if !(self.hysteresis().is_finite() && self.hysteresis() >= 0.0) {
+            problems.push(format_args!("{}.hysteresis", path), "must be 0 or more");
+        }
+        if !(0.0..=100.0).contains(&self.duty()) {
+            problems.push(format_args!("{}.duty", path), "must be between 0 and 100");
+        }
+    }
+
+
+    /// Whether the fan should run for `humidity`, given whether it already
+    /// does because of it.
+    pub fn is_humid(&self, humidity: f32, humid: bool) -> bool {
+        match self.max {
+            Some(max) if humid => humidity > max - self.hysteresis(),
+            Some(max) => humidity > max,
+            None => false,
+        }
+    }
+}
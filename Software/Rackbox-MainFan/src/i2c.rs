@@ -0,0 +1,428 @@
+//! I2C environmental sensors: Bosch BME280 (temperature, humidity, pressure)
+//! and Sensirion SHT31 (temperature, humidity).
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use rppal::i2c::I2c;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Problems;
+use crate::sensor::{Environment, Sensor};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum I2cSensorKind {
+    Bme280,
+    Sht31,
+}
+
+impl I2cSensorKind {
+    /// Addresses the chip can be strapped to; the first is the default.
+    fn addresses(&self) -> [u16; 2] {
+        match self {
+            I2cSensorKind::Bme280 => [0x76, 0x77],
+            I2cSensorKind::Sht31 => [0x44, 0x45],
+        }
+    }
+
+    /// Time a measurement takes, waited out before reading it.
+    pub fn conversion_time(&self) -> Duration {
+        match self {
+            I2cSensorKind::Bme280 => Duration::from_millis(10),
+            I2cSensorKind::Sht31 => Duration::from_millis(16),
+        }
+    }
+
+    /// Operating range of the chip in °C.
+    fn range(&self) -> (f32, f32) {
+        match self {
+            I2cSensorKind::Bme280 => (-40.0, 85.0),
+            I2cSensorKind::Sht31 => (-40.0, 125.0),
+        }
+    }
+}
+
+impl fmt::Display for I2cSensorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cSensorKind::Bme280 => write!(f, "bme280"),
+            I2cSensorKind::Sht31 => write!(f, "sht31"),
+        }
+    }
+}
+
+/// `[[i2c_sensor]]` entry in config.toml.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct I2cSensorConfig {
+    pub name: String,
+    pub kind: I2cSensorKind,
+    /// I2C bus number, 1 on the Pi's header pins.
+    pub bus: Option<u8>,
+    /// 7-bit address; defaults to 0x76 for the BME280 and 0x44 for the SHT31.
+    pub address: Option<u16>,
+    pub location: Option<String>,
+}
+
+impl I2cSensorConfig {
+    pub fn bus(&self) -> u8 {
+        self.bus.unwrap_or(1)
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address.unwrap_or(self.kind.addresses()[0])
+    }
+
+    pub fn resolved(&self) -> Self {
+        I2cSensorConfig {
+            bus: Some(self.bus()),
+            address: Some(self.address()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.name.trim().is_empty() {
+            problems.push(format_args!("{}.name", path), "must not be empty");
+        }
+        let addresses = self.kind.addresses();
+        if !addresses.contains(&self.address()) {
+            problems.push(format_args!("{}.address", path), format_args!(
+                "the {} answers at {:#04x} or {:#04x}", self.kind, addresses[0], addresses[1]));
+        }
+    }
+}
+
+/// Register access to one I2C device: rppal's `I2c` on the Pi, a fake in tests.
+pub trait I2cDevice: Send {
+    fn write(&mut self, bytes: &[u8]) -> Result<()>;
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()>;
+    fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<()>;
+}
+
+impl I2cDevice for I2c {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        I2c::write(self, bytes)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let read = I2c::read(self, buffer)?;
+        if read < buffer.len() {
+            return Err(anyhow::anyhow!("short read: {} of {} bytes", read, buffer.len()));
+        }
+        Ok(())
+    }
+
+    fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+        I2c::write_read(self, bytes, buffer)?;
+        Ok(())
+    }
+}
+
+/// One measurement; pressure in hPa.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Measurement {
+    temperature: f32,
+    humidity: f32,
+    pressure: Option<f32>,
+}
+
+/// BME280 trimming parameters from its NVM.
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    t: [f64; 3],
+    p: [f64; 9],
+    h: [f64; 6],
+}
+
+const BME280_CHIP_ID: u8 = 0x60;
+
+impl Calibration {
+    fn read(device: &mut dyn I2cDevice) -> Result<Self> {
+        let mut id = [0u8; 1];
+        device.write_read(&[0xD0], &mut id)?;
+        if id[0] != BME280_CHIP_ID {
+            // 0x58 is the BMP280, which has no humidity sensor.
+            return Err(anyhow::anyhow!("not a BME280 (chip id {:#04x})", id[0]));
+        }
+
+        let mut tp = [0u8; 24];
+        device.write_read(&[0x88], &mut tp)?;
+        let mut h1 = [0u8; 1];
+        device.write_read(&[0xA1], &mut h1)?;
+        let mut h = [0u8; 7];
+        device.write_read(&[0xE1], &mut h)?;
+
+        let unsigned = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        let signed = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        Ok(Calibration {
+            t: [unsigned(0), signed(2), signed(4)],
+            p: [unsigned(6), signed(8), signed(10), signed(12), signed(14),
+                signed(16), signed(18), signed(20), signed(22)],
+            h: [
+                h1[0] as f64,
+                i16::from_le_bytes([h[0], h[1]]) as f64,
+                h[2] as f64,
+                ((h[3] as i8 as i16) << 4 | (h[4] & 0x0F) as i16) as f64,
+                ((h[5] as i8 as i16) << 4 | (h[4] >> 4) as i16) as f64,
+                h[6] as i8 as f64,
+            ],
+        })
+    }
+
+    /// Datasheet compensation (floating point) of raw ADC values.
+    fn compensate(&self, adc_t: f64, adc_p: f64, adc_h: f64) -> Measurement {
+        let [t1, t2, t3] = self.t;
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0).powi(2) * t3;
+        let t_fine = var1 + var2;
+
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p1;
+        let pressure = (var1 != 0.0).then(|| {
+            let p = ((1048576.0 - adc_p) - var2 / 4096.0) * 6250.0 / var1;
+            p + (p9 * p * p / 2147483648.0 + p * p8 / 32768.0 + p7) / 16.0
+        });
+
+        let [h1, h2, h3, h4, h5, h6] = self.h;
+        let var_h = t_fine - 76800.0;
+        let var_h = (adc_h - (h4 * 64.0 + h5 / 16384.0 * var_h))
+            * (h2 / 65536.0 * (1.0 + h6 / 67108864.0 * var_h * (1.0 + h3 / 67108864.0 * var_h)));
+        let humidity = var_h * (1.0 - h1 * var_h / 524288.0);
+
+        Measurement {
+            temperature: (t_fine / 5120.0) as f32,
+            humidity: humidity.clamp(0.0, 100.0) as f32,
+            pressure: pressure.map(|pa| (pa / 100.0) as f32),
+        }
+    }
+}
+
+/// One forced-mode conversion with 1x oversampling.
+fn measure_bme280(device: &mut dyn I2cDevice, calibration: &Calibration) -> Result<Measurement> {
+    // ctrl_hum only takes effect after a write to ctrl_meas.
+    device.write(&[0xF2, 0x01])?;
+    device.write(&[0xF4, 0x25])?;
+    thread::sleep(I2cSensorKind::Bme280.conversion_time());
+
+    let mut data = [0u8; 8];
+    device.write_read(&[0xF7], &mut data)?;
+    let adc_p = (data[0] as u32) << 12 | (data[1] as u32) << 4 | (data[2] as u32) >> 4;
+    let adc_t = (data[3] as u32) << 12 | (data[4] as u32) << 4 | (data[5] as u32) >> 4;
+    let adc_h = (data[6] as u32) << 8 | data[7] as u32;
+    Ok(calibration.compensate(adc_t as f64, adc_p as f64, adc_h as f64))
+}
+
+/// Sensirion CRC-8: polynomial 0x31, initial value 0xFF.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 { crc << 1 ^ 0x31 } else { crc << 1 }
+        })
+    })
+}
+
+/// Single-shot measurement, high repeatability, no clock stretching.
+fn measure_sht31(device: &mut dyn I2cDevice) -> Result<Measurement> {
+    device.write(&[0x24, 0x00])?;
+    thread::sleep(I2cSensorKind::Sht31.conversion_time());
+
+    let mut data = [0u8; 6];
+    device.read(&mut data)?;
+    for word in data.chunks(3) {
+        if crc8(&word[..2]) != word[2] {
+            return Err(anyhow::anyhow!("SHT31 CRC check failed"));
+        }
+    }
+    let raw_t = u16::from_be_bytes([data[0], data[1]]) as f32;
+    let raw_h = u16::from_be_bytes([data[3], data[4]]) as f32;
+    Ok(Measurement {
+        temperature: -45.0 + 175.0 * raw_t / 65535.0,
+        humidity: 100.0 * raw_h / 65535.0,
+        pressure: None,
+    })
+}
+
+/// Dew point in °C (Magnus formula) for a temperature and relative humidity.
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    const A: f32 = 17.62;
+    const B: f32 = 243.12;
+    let gamma = (humidity.max(0.1) / 100.0).ln() + A * temperature / (B + temperature);
+    B * gamma / (A - gamma)
+}
+
+/// BME280 or SHT31 on an I2C bus. The device is opened on the first read and
+/// again after an error, so a sensor plugged in late is picked up.
+pub struct I2cSensor {
+    config: I2cSensorConfig,
+    device: Option<Box<dyn I2cDevice>>,
+    calibration: Option<Calibration>,
+    environment: Environment,
+}
+
+impl I2cSensor {
+    pub fn new(config: I2cSensorConfig) -> Self {
+        match &config.location {
+            Some(location) => info!("Sensor '{}' ({}) is a {} at {:#04x} on I2C bus {}",
+                                    config.name, location, config.kind, config.address(), config.bus()),
+            None => info!("Sensor '{}' is a {} at {:#04x} on I2C bus {}",
+                          config.name, config.kind, config.address(), config.bus()),
+        }
+        I2cSensor { config, device: None, calibration: None, environment: Environment::default() }
+    }
+
+    #[cfg(test)]
+    fn with_device(config: I2cSensorConfig, device: Box<dyn I2cDevice>) -> Self {
+        I2cSensor { device: Some(device), ..Self::new(config) }
+    }
+
+    fn open(&self) -> Result<Box<dyn I2cDevice>> {
+        let mut i2c = I2c::with_bus(self.config.bus())
+            .with_context(|| format!("Failed to open I2C bus {}", self.config.bus()))?;
+        i2c.set_slave_address(self.config.address())?;
+        Ok(Box::new(i2c))
+    }
+
+    fn measure(&mut self) -> Result<Measurement> {
+        let mut device = match self.device.take() {
+            Some(device) => device,
+            None => self.open()?,
+        };
+        let measurement = match self.config.kind {
+            I2cSensorKind::Bme280 => {
+                let calibration = match self.calibration {
+                    Some(calibration) => calibration,
+                    None => Calibration::read(device.as_mut())?,
+                };
+                self.calibration = Some(calibration);
+                measure_bme280(device.as_mut(), &calibration)?
+            }
+            I2cSensorKind::Sht31 => measure_sht31(device.as_mut())?,
+        };
+        self.device = Some(device);
+        Ok(measurement)
+    }
+}
+
+impl Sensor for I2cSensor {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn read_temperature(&mut self) -> Result<f32> {
+        match self.measure() {
+            Ok(measurement) => {
+                self.environment = Environment {
+                    humidity: Some(measurement.humidity),
+                    pressure: measurement.pressure,
+                };
+                Ok(measurement.temperature)
+            }
+            Err(e) => {
+                // Re-read the calibration too: the device may have been swapped.
+                self.calibration = None;
+                self.environment = Environment::default();
+                warn!("Sensor '{}': {} read failed: {:#}", self.config.name, self.config.kind, e);
+                Err(e).with_context(|| format!("{} at {:#04x}", self.config.kind, self.config.address()))
+            }
+        }
+    }
+
+    fn environment(&self) -> Environment {
+        self.environment
+    }
+
+    fn range(&self) -> Option<(f32, f32)> {
+        Some(self.config.kind.range())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Register file of a BME280, or the next read of an SHT31.
+    struct FakeDevice {
+        registers: [u8; 256],
+        reply: Vec<u8>,
+    }
+
+    impl I2cDevice for FakeDevice {
+        fn write(&mut self, _bytes: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+            buffer.copy_from_slice(&self.reply[..buffer.len()]);
+            Ok(())
+        }
+
+        fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+            let start = bytes[0] as usize;
+            buffer.copy_from_slice(&self.registers[start..start + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    fn config(kind: I2cSensorKind) -> I2cSensorConfig {
+        I2cSensorConfig { name: "basement".to_string(), kind, bus: None, address: None, location: None }
+    }
+
+    #[test]
+    fn bme280_compensates_with_the_datasheet_example() {
+        let mut registers = [0u8; 256];
+        registers[0xD0] = BME280_CHIP_ID;
+        let words: [i32; 12] = [27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7,
+                                15500, -14600, 6000];
+        for (i, word) in words.iter().enumerate() {
+            registers[0x88 + 2 * i..0x8A + 2 * i].copy_from_slice(&(*word as u16).to_le_bytes());
+        }
+        // H1 75, H2 362, H3 0, H4 313, H5 50, H6 30.
+        registers[0xA1] = 75;
+        registers[0xE1..0xE8].copy_from_slice(&[0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 30]);
+        // adc_P 415148, adc_T 519888, adc_H 30000.
+        registers[0xF7..0xFF].copy_from_slice(&[0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x75, 0x30]);
+
+        let device = FakeDevice { registers, reply: Vec::new() };
+        let mut sensor = I2cSensor::with_device(config(I2cSensorKind::Bme280), Box::new(device));
+        let temp = sensor.read_temperature().unwrap();
+        let environment = sensor.environment();
+
+        assert!((temp - 25.08).abs() < 0.01);
+        assert!((environment.pressure.unwrap() - 1006.53).abs() < 0.01);
+        assert!((environment.humidity.unwrap() - 55.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn sht31_checks_crc_and_converts() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+
+        // 0x6666 is 25°C, 0x8000 is 50% RH.
+        let reply = vec![0x66, 0x66, crc8(&[0x66, 0x66]), 0x80, 0x00, crc8(&[0x80, 0x00])];
+        let device = FakeDevice { registers: [0; 256], reply: reply.clone() };
+        let mut sensor = I2cSensor::with_device(config(I2cSensorKind::Sht31), Box::new(device));
+        assert!((sensor.read_temperature().unwrap() - 25.0).abs() < 0.01);
+        assert!((sensor.environment().humidity.unwrap() - 50.0).abs() < 0.01);
+        assert_eq!(sensor.environment().pressure, None);
+
+        let mut corrupted = reply;
+        corrupted[5] ^= 1;
+        let mut device = FakeDevice { registers: [0; 256], reply: corrupted };
+        assert!(measure_sht31(&mut device).is_err());
+    }
+
+    #[test]
+    fn dew_point_follows_the_magnus_formula() {
+        assert!((dew_point(25.0, 60.0) - 16.69).abs() < 0.01);
+        assert!((dew_point(20.0, 100.0) - 20.0).abs() < 0.01);
+    }
+}
@@ -1,301 +1,691 @@
-use anyhow::{Context, Result};
-use glob::glob;
-use log::{debug, error, info, warn, LevelFilter};
-use rppal::gpio::{Gpio, OutputPin};
-use serde::Deserialize;
-use std::fs;
-use std::io::Read;
+mod alarm;
+mod config;
+mod control;
+mod cycling;
+mod failsafe;
+mod fan;
+mod filter;
+mod history;
+mod hook;
+mod humidity;
+mod i2c;
+mod manual;
+mod metrics;
+mod mqtt;
+mod pid;
+mod profile;
+mod replay;
+mod sensor;
+mod sim;
+mod sysfs;
+mod tach;
+mod watchdog;
+mod zone;
+
+use anyhow::Result;
+use log::{error, info, warn, LevelFilter};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use syslog::{BasicLogger, Facility, Formatter3164};
-use ctrlc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-
-#[derive(Debug, Deserialize, Clone)]
-struct Config {
-    temp_minima: f32,
-    temp_maxima: f32,
-    fan_gpio: u8,
-    sensor_path: Option<String>,
-    check_interval_secs: Option<u64>,
+use std::sync::Arc;
+use config::Config;
+use control::{ControlServer, SensorStatus};
+use history::HistoryStore;
+use metrics::MetricsServer;
+use mqtt::MqttLink;
+use rackbox_notify::Notifier;
+use sensor::{SensorReading, SensorSet};
+use watchdog::Watchdog;
+use zone::{Zone, ZoneSettings};
+
+/// Set from the SIGHUP handler, consumed by the control loop.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sighup(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
 }
 
-impl Config {
-    fn load(path: &str) -> Result<Self> {
-        let config_content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {}", path))?;
+struct Daemon {
+    config_path: String,
+    config: Config,
+    sensors: SensorSet,
+    zones: Vec<Zone>,
+    last_check: Option<Instant>,
+    running: Arc<AtomicBool>,
+    watchdog: Option<Watchdog>,
+    notifier: Notifier,
+    control: Option<ControlServer>,
+    /// Sensor readings of the last cycle, for the control socket.
+    readings: Vec<SensorStatus>,
+    started: Instant,
+    metrics: Option<MetricsServer>,
+    /// Failed reads per sensor since start.
+    read_errors: BTreeMap<String, u64>,
+    cycles: u64,
+    mqtt: Option<MqttLink>,
+    history: Option<HistoryStore>,
+}
 
-        let config: Config = toml::from_str(&config_content)
-            .with_context(|| "Failed to parse config file")?;
+impl Daemon {
+    fn new(config_path: String, config: Config) -> Result<Self> {
+        info!("=== RackFan Daemon Starting ===");
 
-        Ok(config)
+        let (sensors, settings) = Self::prepare(&config)?;
+        let zones = settings.into_iter()
+            .map(Zone::new)
+            .collect::<Result<Vec<_>>>()?;
+        let watchdog = config.watchdog.as_ref().map(Watchdog::open).transpose()?;
+
+        let mut daemon = Self::with_parts(config_path, config, sensors, zones);
+        daemon.watchdog = watchdog;
+        daemon.notifier = Notifier::from_env();
+        // Fan control must not depend on the socket: run without it on error.
+        daemon.control = ControlServer::bind(daemon.config.control_socket())
+            .inspect_err(|e| warn!("Control socket disabled: {:#}", e))
+            .ok();
+        if let Some(addr) = &daemon.config.metrics_listen {
+            daemon.metrics = MetricsServer::bind(addr)
+                .inspect_err(|e| warn!("Metrics endpoint disabled: {:#}", e))
+                .ok();
+        }
+        if let Some(history) = &daemon.config.history {
+            daemon.history = HistoryStore::open(history, history::now())
+                .inspect_err(|e| warn!("History disabled: {:#}", e))
+                .ok();
+        }
+        if let Some(mqtt) = &daemon.config.mqtt {
+            let zones: Vec<String> = daemon.zones.iter()
+                .map(|zone| zone.settings.name.clone())
+                .collect();
+            daemon.mqtt = MqttLink::connect(mqtt, &daemon.sensors.names(), &zones)
+                .inspect_err(|e| warn!("MQTT disabled: {:#}", e))
+                .ok();
+        }
+        Ok(daemon)
     }
 
-    fn default() -> Self {
-        Config {
-            temp_minima: 25.0,
-            temp_maxima: 35.0,
-            fan_gpio: 17,
-            sensor_path: None,
-            check_interval_secs: Some(5),
+    /// Daemon over already constructed sensors and zones, real or simulated.
+    fn with_parts(config_path: String, config: Config, sensors: SensorSet, zones: Vec<Zone>) -> Self {
+        Daemon {
+            config_path,
+            config,
+            sensors,
+            zones,
+            last_check: None,
+            running: Arc::new(AtomicBool::new(true)),
+            watchdog: None,
+            notifier: Notifier::disabled(),
+            control: None,
+            readings: Vec::new(),
+            started: Instant::now(),
+            metrics: None,
+            read_errors: BTreeMap::new(),
+            cycles: 0,
+            mqtt: None,
+            history: None,
         }
     }
-}
 
-struct TemperatureSensor {
-    device_file: String,
-}
+    /// Opens the sensors and resolves the zones of a config, checking that
+    /// every sensor a zone refers to exists.
+    fn prepare(config: &Config) -> Result<(SensorSet, Vec<ZoneSettings>)> {
+        let sensors = SensorSet::new(config.sensor_path.as_deref(), &config.sensors,
+                                     &config.onewire, &config.i2c_sensors,
+                                     &config.sysfs_sensors)?
+            .with_filter(&config.filter);
+        let settings = config.zones()?;
+        Self::check_zone_sensors(&sensors, &settings)?;
+        Ok((sensors, settings))
+    }
 
-impl TemperatureSensor {
-    fn new(device_file: Option<&str>) -> Result<Self> {
-        let device_file = match device_file {
-            Some(path) => path.to_string(),
-            None => Self::find_sensor()?,
-        };
+    fn check_zone_sensors(sensors: &SensorSet, settings: &[ZoneSettings]) -> Result<()> {
+        for zone in settings {
+            let unknown = zone.sensors.iter()
+                .map(String::as_str)
+                .chain(zone.sensor_policy.required_sensors())
+                .find(|name| !sensors.contains(name));
+            if let Some(name) = unknown {
+                return Err(anyhow::anyhow!("Zone '{}' refers to unknown sensor '{}'",
+                                           zone.name, name));
+            }
+        }
+        Ok(())
+    }
 
-        info!("Sensor initialized at: {}", device_file);
-        Ok(TemperatureSensor { device_file })
+    fn setup_signal_handler(&self) {
+        let running = Arc::clone(&self.running);
+        
+        ctrlc::set_handler(move || {
+            info!("Shutting down...");
+            running.store(false, Ordering::SeqCst);
+        }).expect("Error setting Ctrl-C handler");
+
+        unsafe {
+            libc::signal(libc::SIGHUP, handle_sighup as *const () as libc::sighandler_t);
+        }
     }
 
-    fn find_sensor() -> Result<String> {
-        let pattern = "/sys/bus/w1/devices/28*/w1_slave";
-
-        for entry in glob(pattern)? {
-            match entry {
-                Ok(path) => {
-                    if path.exists() {
-                        let path_str = path.to_string_lossy().into_owned();
-                        info!("Found DS18B20 at: {}", path_str);
-                        return Ok(path_str);
-                    }
+    /// Re-reads the config file. Thresholds, curves, PID gains, sensor
+    /// settings and the interval apply immediately; anything that would
+    /// re-initialise a fan output is rejected and the previous config kept.
+    fn reload(&mut self) {
+        info!("SIGHUP received, reloading {}", self.config_path);
+
+        let result = Config::load(&self.config_path).and_then(|config| {
+            let (sensors, settings) = Self::prepare(&config)?;
+
+            let names: Vec<&str> = settings.iter().map(|z| z.name.as_str()).collect();
+            let current: Vec<&str> = self.zones.iter().map(|z| z.settings.name.as_str()).collect();
+            if names != current {
+                return Err(anyhow::anyhow!(
+                    "zones changed ({} -> {}), restart the daemon to apply",
+                    current.join(", "), names.join(", ")));
+            }
+            for (zone, new) in self.zones.iter().zip(&settings) {
+                if zone.settings.output_key() != new.output_key() {
+                    return Err(anyhow::anyhow!(
+                        "zone '{}': fan output changed, restart the daemon to apply", new.name));
                 }
-                Err(e) => warn!("Error reading sensor path: {}", e),
             }
-        }
 
-        Err(anyhow::anyhow!("No DS18B20 sensor found"))
+            if config.watchdog != self.config.watchdog {
+                return Err(anyhow::anyhow!("watchdog changed, restart the daemon to apply"));
+            }
+            if config.history != self.config.history {
+                return Err(anyhow::anyhow!("history changed, restart the daemon to apply"));
+            }
+            if config.mqtt != self.config.mqtt {
+                return Err(anyhow::anyhow!("mqtt changed, restart the daemon to apply"));
+            }
+            if config.metrics_listen != self.config.metrics_listen {
+                return Err(anyhow::anyhow!(
+                    "metrics_listen changed, restart the daemon to apply"));
+            }
+            if config.control_socket() != self.config.control_socket() {
+                return Err(anyhow::anyhow!(
+                    "control_socket changed, restart the daemon to apply"));
+            }
+
+            Ok((config, sensors, settings))
+        });
+
+        match result {
+            Ok((config, sensors, settings)) => {
+                for (zone, new) in self.zones.iter_mut().zip(settings) {
+                    zone.reconfigure(new);
+                }
+                self.sensors = sensors;
+                self.config = config;
+                info!("Config reloaded, monitoring every {} seconds",
+                      self.config.check_interval_secs());
+            }
+            Err(e) => error!("Config reload failed, keeping previous config: {:#}", e),
+        }
     }
 
-    fn read_temperature(&self) -> Result<f32> {
-        let mut file = fs::File::open(&self.device_file)
-            .with_context(|| format!("Failed to open sensor: {}", self.device_file))?;
+    fn run(self) -> Result<()> {
+        self.setup_signal_handler();
+        self.run_loop()
+    }
 
-        let mut content = String::new();
-        file.read_to_string(&mut content)
-            .context("Failed to read sensor data")?;
+    /// Control loop until `running` is cleared; every fan is switched off on
+    /// the way out.
+    fn run_loop(mut self) -> Result<()> {
+        info!("Monitoring every {} seconds", 
+              self.config.check_interval_secs());
+        self.notifier.ready();
 
-        let lines: Vec<&str> = content.lines().collect();
+        while self.running.load(Ordering::SeqCst) {
+            if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+                self.reload();
+            }
 
-        if lines.len() < 2 {
-            error!("Invalid sensor data format");
-            return Err(anyhow::anyhow!("Invalid sensor data format"));
+            self.check_temperature()?;
+            self.notifier.watchdog();
+            
+            let next_check = Instant::now()
+                + Duration::from_secs(self.config.check_interval_secs());
+            while self.running.load(Ordering::SeqCst) && !RELOAD_REQUESTED.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now >= next_check {
+                    break;
+                }
+                self.wait((next_check - now).min(Duration::from_secs(1)));
+            }
         }
 
-        if !lines[0].trim().ends_with("YES") {
-            error!("Sensor CRC check failed");
-            return Err(anyhow::anyhow!("Sensor CRC check failed"));
+        self.notifier.stopping();
+        self.shutdown();
+        if let Some(watchdog) = self.watchdog.take() {
+            watchdog.close();
         }
+        if let Some(mqtt) = self.mqtt.take() {
+            mqtt.close();
+        }
+        info!("Daemon stopped");
+        Ok(())
+    }
 
-        if let Some(temp_line) = lines.get(1) {
-            if let Some(pos) = temp_line.find("t=") {
-                let temp_str = &temp_line[pos + 2..];
-                let temp_millic = temp_str.parse::<f32>()
-                    .context("Failed to parse temperature")?;
-
-                let temp_c = temp_millic / 1000.0;
-                debug!("Raw temp: {} -> {}°C", temp_millic, temp_c);
-                return Ok(temp_c);
+    /// Sleeps for `step`, answering control socket requests meanwhile.
+    /// MQTT commands queued since the last step are applied first.
+    fn wait(&mut self, step: Duration) {
+        let commands = self.mqtt.as_ref().map(MqttLink::commands).unwrap_or_default();
+        if !commands.is_empty() {
+            for request in commands {
+                let reply = self.handle_request(Ok(request));
+                if let Some(error) = reply["error"].as_str() {
+                    warn!("MQTT command rejected: {}", error);
+                }
             }
+            self.publish_mqtt();
         }
 
-        error!("Temperature data not found");
-        Err(anyhow::anyhow!("Temperature data not found"))
+        let Some(control) = &self.control else {
+            thread::sleep(step);
+            return;
+        };
+        if let Some(pending) = control.next(step) {
+            let reply = self.handle_request(pending.request);
+            let _ = pending.reply.send(reply);
+        }
     }
-}
 
-struct FanController {
-    pin: OutputPin,
-    current_state: bool,
-}
+    fn shutdown(&self) {
+        for zone in &self.zones {
+            zone.turn_off();
+        }
+    }
 
-impl FanController {
-    fn new(gpio_num: u8) -> Result<Self> {
-        let gpio = Gpio::new()?;
-        let mut pin = gpio.get(gpio_num)?.into_output();
-        pin.set_low();
+    fn check_temperature(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let dt = match self.last_check {
+            Some(last) => now.duration_since(last).as_secs_f32(),
+            None => self.config.check_interval_secs() as f32,
+        };
+        self.last_check = Some(now);
 
-        info!("Fan controller on GPIO {}", gpio_num);
-        Ok(FanController {
-            pin,
-            current_state: false,
-        })
+        self.sensors.rescan();
+        self.apply_profiles(profile::local_minute());
+        self.check_temperature_after(dt)
     }
 
-    fn turn_on(&mut self) {
-        if !self.current_state {
-            self.pin.set_high();
-            self.current_state = true;
-            info!("Fan turned ON (GPIO {})", self.pin.pin());
+    /// Activates the profiles covering `minute` after local midnight.
+    fn apply_profiles(&mut self, minute: u32) {
+        for zone in &mut self.zones {
+            zone.set_profile(profile::active(&self.config.profiles, &zone.settings.name, minute));
         }
     }
 
-    fn turn_off(&mut self) {
-        if self.current_state {
-            self.pin.set_low();
-            self.current_state = false;
-            info!("Fan turned OFF (GPIO {})", self.pin.pin());
+    /// One control cycle, `dt` seconds after the previous one.
+    fn check_temperature_after(&mut self, dt: f32) -> Result<()> {
+        let started = Instant::now();
+        let readings = self.sensors.read_all();
+        log_readings(&readings);
+        let previous = std::mem::replace(
+            &mut self.readings, readings.iter().map(SensorStatus::from).collect());
+        for reading in &readings {
+            let errors = self.read_errors.entry(reading.name.clone()).or_default();
+            let Err(e) = &reading.temp else {
+                continue;
+            };
+            *errors += 1;
+            let was_failing = previous.iter()
+                .any(|status| status.name == reading.name && status.error.is_some());
+            if !was_failing {
+                self.config.hooks.run("on_sensor_error", &[
+                    ("RACKFAN_SENSOR", reading.name.clone()),
+                    ("RACKFAN_ERROR", format!("{:#}", e)),
+                    ("RACKFAN_ERRORS", errors.to_string()),
+                ]);
+            }
+        }
+
+        for zone in &mut self.zones {
+            zone.check(&readings, dt);
+        }
+
+        self.cycles += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.publish(self.render_metrics(started.elapsed()));
+        }
+
+        self.publish_mqtt();
+        if let Some(history) = self.history.as_mut() {
+            let zones: Vec<_> = self.zones.iter().map(Zone::status).collect();
+            history.record(history::now(), &self.readings, &zones);
         }
+
+        let status: Vec<String> = self.zones.iter().map(Zone::summary).collect();
+        self.notifier.status(&status.join(", "));
+
+        // Only a cycle that read a sensor and drove every fan counts as alive.
+        let healthy = readings.iter().any(|r| r.temp.is_ok())
+            && self.zones.iter().all(Zone::output_ok);
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            if healthy {
+                watchdog.pet();
+            } else {
+                warn!("Control cycle failed, watchdog not fed");
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(PartialEq)]
-enum FanAction {
-    Off,
-    On,
-    NoChange,
+impl Daemon {
+    fn publish_mqtt(&self) {
+        if let Some(mqtt) = &self.mqtt {
+            let zones: Vec<_> = self.zones.iter().map(Zone::status).collect();
+            mqtt.publish_state(&self.readings, &zones);
+        }
+    }
 }
 
-struct Daemon {
-    config: Config,
-    sensor: TemperatureSensor,
-    fan: Arc<Mutex<FanController>>,
-    last_action: FanAction,
-    running: Arc<AtomicBool>,
+/// One line per cycle with every sensor, e.g. `inlet=24.1°C exhaust=29.3°C`.
+fn log_readings(readings: &[SensorReading]) {
+    let summary: Vec<String> = readings.iter()
+        .map(|r| match &r.temp {
+            Ok(temp) => match r.environment.humidity {
+                Some(humidity) => format!("{}={:.1}°C/{:.0}%RH", r.name, temp, humidity),
+                None => format!("{}={:.1}°C", r.name, temp),
+            },
+            Err(e) => {
+                warn!("Sensor '{}' read error: {}", r.name, e);
+                format!("{}=error", r.name)
+            }
+        })
+        .collect();
+    info!("Sensors: {}", summary.join(" "));
 }
 
-impl Daemon {
-    fn new(config: Config) -> Result<Self> {
-        // Setup syslog
-        let formatter = Formatter3164 {
-            facility: Facility::LOG_DAEMON,
-            hostname: None,
-            process: "rackfan_daemon".into(),
-            pid: std::process::id(),
-        };
-
-        let logger = syslog::unix(formatter)?;
-        let logger = BasicLogger::new(logger);
-        
-        log::set_boxed_logger(Box::new(logger))
-            .map(|()| log::set_max_level(LevelFilter::Info))?;
+fn setup_logger() -> Result<()> {
+    let formatter = Formatter3164 {
+        facility: Facility::LOG_DAEMON,
+        hostname: None,
+        process: "rackfan_daemon".into(),
+        pid: std::process::id(),
+    };
 
-        info!("=== RackFan Daemon Starting ===");
-        info!("Min: {}°C, Max: {}°C, GPIO: {}", 
-              config.temp_minima, config.temp_maxima, config.fan_gpio);
+    let logger = syslog::unix(formatter)?;
+    let logger = BasicLogger::new(logger);
+    
+    log::set_boxed_logger(Box::new(logger))
+        .map(|()| log::set_max_level(LevelFilter::Info))?;
+    Ok(())
+}
 
-        let sensor = TemperatureSensor::new(config.sensor_path.as_deref())?;
-        let fan = Arc::new(Mutex::new(FanController::new(config.fan_gpio)?));
+const DEFAULT_CONFIG_PATH: &str = "/etc/rackfan/config.toml";
 
-        Ok(Daemon {
-            config,
-            sensor,
-            fan,
-            last_action: FanAction::NoChange,
-            running: Arc::new(AtomicBool::new(true)),
-        })
+/// `--check-config [path]`: validates a config and prints it with all defaults
+/// filled in, without touching GPIO or syslog. Exits non-zero if invalid.
+fn check_config(path: &str) -> ! {
+    match Config::load(path).and_then(|config| config.effective_toml()) {
+        Ok(effective) => {
+            println!("# {}: OK", path);
+            print!("{}", effective);
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
     }
+}
 
-    fn setup_signal_handler(&self) {
-        let running = Arc::clone(&self.running);
-        let fan = Arc::clone(&self.fan);
-        
-        ctrlc::set_handler(move || {
-            info!("Shutting down...");
-            if let Ok(mut fan) = fan.lock() {
-                fan.turn_off();
-            }
-            running.store(false, Ordering::SeqCst);
-        }).expect("Error setting Ctrl-C handler");
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("--check-config") => check_config(args.get(2).map_or(DEFAULT_CONFIG_PATH, String::as_str)),
+        Some("replay") => return replay::run(&args[2..]),
+        Some("history") => return history::run(&args[2..]),
+        Some("sensors") => return sysfs::run(&args[2..]),
+        _ => {}
     }
 
-    fn run(mut self) -> Result<()> {
-        self.setup_signal_handler();
-        
-        info!("Monitoring every {} seconds", 
-              self.config.check_interval_secs.unwrap_or(5));
-
-        while self.running.load(Ordering::SeqCst) {
-            self.check_temperature()?;
-            
-            let interval = self.config.check_interval_secs.unwrap_or(5);
-            for _ in 0..interval {
-                if !self.running.load(Ordering::SeqCst) {
-                    break;
-                }
-                thread::sleep(Duration::from_secs(1));
-            }
-        }
+    let config_path = args.get(1)
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
 
-        info!("Daemon stopped");
-        Ok(())
-    }
+    setup_logger()?;
 
-    fn check_temperature(&mut self) -> Result<()> {
-        match self.sensor.read_temperature() {
-            Ok(temp) => {
-                let action = if temp < self.config.temp_minima {
-                    FanAction::Off
-                } else if temp > self.config.temp_maxima {
-                    FanAction::On
-                } else {
-                    FanAction::NoChange
-                };
-
-                self.apply_action(action, temp);
+    // Only a missing file falls back to the defaults: a config that fails to
+    // parse or validate stops the daemon, so the mistake shows in systemctl.
+    let config = if Path::new(&config_path).exists() {
+        match Config::load(&config_path) {
+            Ok(config) => {
+                info!("Config loaded from: {}", config_path);
+                config
             }
             Err(e) => {
-                error!("Temperature read error: {}", e);
-                if let Ok(mut fan) = self.fan.lock() {
-                    fan.turn_on();
-                }
-                self.last_action = FanAction::On;
+                error!("Config error: {:#}", e);
+                eprintln!("Config error: {:#}", e);
+                process::exit(1);
             }
         }
-        Ok(())
+    } else {
+        warn!("Config file {} not found, using defaults", config_path);
+        Config::default()
+    };
+
+    Daemon::new(config_path, config)?.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmConfig;
+    use crate::cycling::CyclingConfig;
+    use crate::failsafe::FailsafeConfig;
+    use crate::fan::{FanController, FanMode};
+    use crate::hook::HooksConfig;
+    use crate::humidity::HumidityConfig;
+    use crate::manual::ManualConfig;
+    use crate::sensor::{Sensor, SensorPolicy};
+    use crate::sim::{FanLog, RecordingFan, ScriptedSensor, SensorScript};
+
+    fn zone(name: &str, sensor: &str) -> (Zone, FanLog) {
+        let settings = ZoneSettings {
+            name: name.to_string(),
+            sensors: vec![sensor.to_string()],
+            fan_gpio: 0,
+            temp_minima: 25.0,
+            temp_maxima: 30.0,
+            fan_mode: FanMode::OnOff,
+            pwm: None,
+            pid: None,
+            sensor_policy: SensorPolicy::Max,
+            failsafe: FailsafeConfig::default(),
+            cycling: CyclingConfig::default(),
+            alarm: AlarmConfig::default(),
+            humidity: HumidityConfig::default(),
+            manual: ManualConfig::default(),
+            hooks: HooksConfig::default(),
+            tach: None,
+        };
+        let (driver, log) = RecordingFan::new(false);
+        let fan = FanController::with_driver(name.to_string(), Box::new(driver));
+        (Zone::with_fan(settings, fan), log)
     }
 
-    fn apply_action(&mut self, action: FanAction, temp: f32) {
-        if action != self.last_action {
-            match action {
-                FanAction::Off => {
-                    if let Ok(mut fan) = self.fan.lock() {
-                        fan.turn_off();
-                    }
-                    info!("Rackbox fan desligado (Temp: {:.1}°C < {:.1}°C)",
-                          temp, self.config.temp_minima);
-                }
-                FanAction::On => {
-                    if let Ok(mut fan) = self.fan.lock() {
-                        fan.turn_on();
-                    }
-                    info!("Rackbox fan ligado (Temp: {:.1}°C > {:.1}°C)",
-                          temp, self.config.temp_maxima);
-                }
-                FanAction::NoChange => {
-                    info!("Temp {:.1}°C within range", temp);
-                }
-            }
-            self.last_action = action;
-        }
+    fn sensor(name: &str) -> (Box<dyn Sensor>, SensorScript) {
+        let (sensor, script) = ScriptedSensor::new(name);
+        (Box::new(sensor), script)
     }
-}
 
-fn main() -> Result<()> {
-    let config_path = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "/etc/rackfan/config.toml".to_string());
-
-    let config = match Config::load(&config_path) {
-        Ok(config) => {
-            println!("Config loaded from: {}", config_path);
-            config
-        }
-        Err(e) => {
-            eprintln!("Config error: {}. Using defaults.", e);
-            Config::default()
-        }
-    };
+    /// Daemon with a "top" and a "psu" zone, each fed by its own sensor.
+    fn daemon() -> (Daemon, [SensorScript; 2], [FanLog; 2]) {
+        let (top_sensor, top_script) = sensor("top");
+        let (psu_sensor, psu_script) = sensor("psu");
+        let (top, top_log) = zone("top", "top");
+        let (psu, psu_log) = zone("psu", "psu");
+
+        let daemon = Daemon::with_parts(
+            "test.toml".to_string(),
+            Config::default(),
+            SensorSet::from_sensors(vec![top_sensor, psu_sensor]),
+            vec![top, psu],
+        );
+        (daemon, [top_script, psu_script], [top_log, psu_log])
+    }
+
+    #[test]
+    fn zones_are_controlled_independently() {
+        let (mut daemon, [top, psu], [top_log, psu_log]) = daemon();
+
+        top.push(32.0);
+        psu.push(20.0);
+        daemon.check_temperature().unwrap();
+        assert_eq!(top_log.transitions(), vec![100.0]);
+        assert!(psu_log.transitions().is_empty());
+
+        top.push(24.0);
+        psu.push(31.0);
+        daemon.check_temperature().unwrap();
+        assert_eq!(top_log.transitions(), vec![100.0, 0.0]);
+        assert_eq!(psu_log.transitions(), vec![100.0]);
+    }
+
+    #[test]
+    fn sensor_error_fails_safe_only_in_affected_zone() {
+        let (mut daemon, [top, psu], [top_log, psu_log]) = daemon();
+
+        top.push(20.0);
+        psu.push_error("no such device");
+        daemon.check_temperature().unwrap();
+
+        assert!(top_log.transitions().is_empty());
+        assert_eq!(psu_log.transitions(), vec![100.0]);
+    }
+
+    #[test]
+    fn shutdown_turns_all_fans_off() {
+        let (mut daemon, [top, psu], [top_log, psu_log]) = daemon();
+
+        top.push(35.0);
+        psu.push(35.0);
+        daemon.check_temperature().unwrap();
+
+        daemon.running.store(false, Ordering::SeqCst);
+        daemon.run_loop().unwrap();
 
-    Daemon::new(config)?.run()
+        assert_eq!(top_log.transitions(), vec![100.0, 0.0]);
+        assert_eq!(psu_log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn watchdog_fed_only_by_healthy_cycles_and_disarmed_on_shutdown() {
+        let (mut daemon, [top, psu], _) = daemon();
+        let path = std::env::temp_dir()
+            .join(format!("rackfan-daemon-watchdog-{}", process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        daemon.watchdog = Some(Watchdog::from_file(file, path.display().to_string()));
+
+        top.push(20.0);
+        psu.push(20.0);
+        daemon.check_temperature().unwrap();
+        top.push_error("no such device");
+        psu.push_error("no such device");
+        daemon.check_temperature().unwrap();
+
+        daemon.running.store(false, Ordering::SeqCst);
+        daemon.run_loop().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0V");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Sends one request line from a client thread while the daemon waits.
+    fn request(daemon: &mut Daemon, path: &std::path::Path, request: &str) -> serde_json::Value {
+        use std::io::{BufRead, BufReader, Write};
+
+        let path = path.to_path_buf();
+        let request = request.to_string();
+        let client = std::thread::spawn(move || {
+            let mut stream = std::os::unix::net::UnixStream::connect(path).unwrap();
+            writeln!(stream, "{}", request).unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            line
+        });
+        daemon.wait(Duration::from_secs(5));
+        serde_json::from_str(&client.join().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn control_socket_reports_state_and_forces_fans() {
+        let (mut daemon, [top, psu], [top_log, psu_log]) = daemon();
+        let path = std::env::temp_dir()
+            .join(format!("rackfan-control-{}.sock", process::id()));
+        daemon.control = Some(ControlServer::bind(path.to_str().unwrap()).unwrap());
+
+        top.push(32.0);
+        psu.push_error("no such device");
+        daemon.check_temperature().unwrap();
+
+        let status = request(&mut daemon, &path, r#"{"command": "status"}"#);
+        assert_eq!(status["ok"], true);
+        assert_eq!(status["sensors"][0]["temperature"], 32.0);
+        assert_eq!(status["sensors"][1]["error"], "no such device");
+        assert_eq!(status["zones"][0]["fan_on"], true);
+        assert_eq!(status["zones"][0]["last_reason"], "temp 32.0°C > 30.0°C");
+
+        let forced = request(&mut daemon, &path,
+                             r#"{"command": "control", "zone": "top", "mode": "off"}"#);
+        assert_eq!(forced["zones"][0]["control"], "off");
+        assert_eq!(top_log.transitions(), vec![100.0, 0.0]);
+
+        // Forced off holds against temperature control, not against fail-safe.
+        top.push(35.0);
+        daemon.check_temperature().unwrap();
+        assert_eq!(top_log.transitions(), vec![100.0, 0.0]);
+        assert_eq!(psu_log.transitions(), vec![100.0]);
+
+        let unknown = request(&mut daemon, &path,
+                              r#"{"command": "thresholds", "zone": "gpu", "temp_minima": 1, "temp_maxima": 2}"#);
+        assert_eq!(unknown["ok"], false);
+        assert_eq!(unknown["error"], "no zone named 'gpu'");
+
+        drop(daemon);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn metrics_count_switches_errors_and_failsafe() {
+        use std::io::{Read, Write};
+
+        let (mut daemon, [top, psu], _) = daemon();
+        let metrics = MetricsServer::bind("127.0.0.1:0").unwrap();
+        let addr = metrics.addr();
+        daemon.metrics = Some(metrics);
+
+        top.push(32.0);
+        psu.push(20.0);
+        daemon.check_temperature().unwrap();
+        top.push(24.0);
+        psu.push_error("no such device");
+        daemon.check_temperature().unwrap();
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for line in [
+            "rackfan_sensor_temperature_celsius{sensor=\"top\"} 24",
+            "rackfan_sensor_read_errors_total{sensor=\"top\"} 0",
+            "rackfan_sensor_read_errors_total{sensor=\"psu\"} 1",
+            "rackfan_fan_on{zone=\"psu\"} 1",
+            "rackfan_fan_switches_total{zone=\"top\"} 2",
+            "rackfan_failsafe_activations_total{zone=\"psu\"} 1",
+            "rackfan_cycles_total 2",
+        ] {
+            assert!(response.contains(line), "missing {:?} in:\n{}", line, response);
+        }
+    }
 }
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::config::Problems;
+
+/// `[manual]` table: limits on fans forced on/off through `rackfanctl`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ManualConfig {
+    /// Longest override accepted; also the length of one given without a
+    /// duration.
+    pub max_duration_secs: Option<u64>,
+    /// Control temperature that cancels an "off" override. Defaults to 10°C
+    /// above the zone's `temp_maxima`.
+    pub off_ceiling: Option<f32>,
+}
+
+impl ManualConfig {
+    pub fn max_duration(&self) -> Duration {
+        Duration::from_secs(self.max_duration_secs.unwrap_or(4 * 3600))
+    }
+
+    pub fn off_ceiling(&self, temp_maxima: f32) -> f32 {
+        self.off_ceiling.unwrap_or(temp_maxima + 10.0)
+    }
+
+    /// `off_ceiling` stays unset, its default follows each zone's thresholds.
+    pub fn resolved(&self) -> Self {
+        ManualConfig { max_duration_secs: Some(self.max_duration().as_secs()), ..self.clone() }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.max_duration_secs == Some(0) {
+            problems.push(format_args!("{}.max_duration_secs", path), "must be at least 1");
+        }
+        if self.off_ceiling.is_some_and(|t| !t.is_finite()) {
+            problems.push(format_args!("{}.off_ceiling", path), "must be a finite number");
+        }
+    }
+}
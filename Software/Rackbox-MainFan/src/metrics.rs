@@ -0,0 +1,209 @@
+//! Prometheus `/metrics` endpoint. The control loop renders the page after
+//! every cycle; a background thread serves the latest copy over plain HTTP.
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::Daemon;
+
+pub struct MetricsServer {
+    addr: SocketAddr,
+    page: Arc<Mutex<String>>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let page = Arc::new(Mutex::new(String::new()));
+
+        let served = Arc::clone(&page);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => serve(stream, &served),
+                    Err(e) => warn!("Metrics accept failed: {}", e),
+                }
+            }
+        });
+
+        let server = MetricsServer { addr, page };
+        info!("Prometheus metrics on http://{}/metrics", server.addr);
+        Ok(server)
+    }
+
+    /// Address actually bound, which differs from the configured one for port 0.
+    #[cfg(test)]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn publish(&self, page: String) {
+        if let Ok(mut current) = self.page.lock() {
+            *current = page;
+        }
+    }
+}
+
+fn serve(stream: TcpStream, page: &Mutex<String>) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(&stream);
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Skip the headers; nothing in them matters here.
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", page.lock().map(|p| p.clone()).unwrap_or_default()),
+        _ => ("404 Not Found", "try /metrics\n".to_string()),
+    };
+    debug!("Metrics request {} -> {}", path, status);
+
+    let mut writer = &stream;
+    let _ = write!(writer,
+                   "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body);
+}
+
+/// Writes the `# HELP` / `# TYPE` header of a metric family.
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Daemon {
+    /// Metrics page for the cycle that just finished.
+    pub(crate) fn render_metrics(&self, loop_duration: Duration) -> String {
+        let mut out = String::new();
+
+        family(&mut out, "rackfan_sensor_temperature_celsius", "gauge",
+               "Last valid reading of each sensor.");
+        for reading in &self.readings {
+            if let Some(temp) = reading.temperature {
+                let _ = writeln!(out, "rackfan_sensor_temperature_celsius{{sensor=\"{}\"}} {}",
+                                 label(&reading.name), temp);
+            }
+        }
+
+        family(&mut out, "rackfan_sensor_read_errors_total", "counter",
+               "Failed sensor reads.");
+        for (sensor, errors) in &self.read_errors {
+            let _ = writeln!(out, "rackfan_sensor_read_errors_total{{sensor=\"{}\"}} {}",
+                             label(sensor), errors);
+        }
+
+        family(&mut out, "rackfan_sensor_humidity_percent", "gauge",
+               "Relative humidity from environmental sensors.");
+        for reading in &self.readings {
+            if let Some(humidity) = reading.humidity {
+                let _ = writeln!(out, "rackfan_sensor_humidity_percent{{sensor=\"{}\"}} {}",
+                                 label(&reading.name), humidity);
+            }
+        }
+
+        family(&mut out, "rackfan_sensor_pressure_pascals", "gauge",
+               "Barometric pressure from environmental sensors.");
+        for reading in &self.readings {
+            if let Some(pressure) = reading.pressure {
+                let _ = writeln!(out, "rackfan_sensor_pressure_pascals{{sensor=\"{}\"}} {}",
+                                 label(&reading.name), pressure * 100.0);
+            }
+        }
+
+        family(&mut out, "rackfan_sensor_dew_point_celsius", "gauge",
+               "Dew point from temperature and humidity.");
+        for reading in &self.readings {
+            if let Some(dew_point) = reading.dew_point {
+                let _ = writeln!(out, "rackfan_sensor_dew_point_celsius{{sensor=\"{}\"}} {}",
+                                 label(&reading.name), dew_point);
+            }
+        }
+
+        let zones: Vec<_> = self.zones.iter().map(|zone| zone.status()).collect();
+
+        family(&mut out, "rackfan_zone_temperature_celsius", "gauge",
+               "Control temperature of each zone after the sensor policy.");
+        for zone in &zones {
+            if let Some(temp) = zone.temperature {
+                let _ = writeln!(out, "rackfan_zone_temperature_celsius{{zone=\"{}\"}} {}",
+                                 label(&zone.name), temp);
+            }
+        }
+
+        family(&mut out, "rackfan_fan_on", "gauge", "1 while the zone's fan is running.");
+        for zone in &zones {
+            let _ = writeln!(out, "rackfan_fan_on{{zone=\"{}\"}} {}",
+                             label(&zone.name), u8::from(zone.fan_on));
+        }
+
+        family(&mut out, "rackfan_fan_duty_percent", "gauge", "Fan duty cycle.");
+        for zone in &zones {
+            let _ = writeln!(out, "rackfan_fan_duty_percent{{zone=\"{}\"}} {}",
+                             label(&zone.name), zone.duty);
+        }
+
+        family(&mut out, "rackfan_fan_switches_total", "counter",
+               "Times the fan was switched on or off.");
+        for zone in &zones {
+            let _ = writeln!(out, "rackfan_fan_switches_total{{zone=\"{}\"}} {}",
+                             label(&zone.name), zone.switches);
+        }
+
+        family(&mut out, "rackfan_failsafe_activations_total", "counter",
+               "Times the zone entered fail-safe after losing its sensors.");
+        for zone in &zones {
+            let _ = writeln!(out, "rackfan_failsafe_activations_total{{zone=\"{}\"}} {}",
+                             label(&zone.name), zone.failsafe_activations);
+        }
+
+        family(&mut out, "rackfan_alarm_level", "gauge",
+               "Alarm level of the zone: 0 normal, 1 warning, 2 critical.");
+        for zone in &zones {
+            let _ = writeln!(out, "rackfan_alarm_level{{zone=\"{}\"}} {}",
+                             label(&zone.name), zone.alarm as u8);
+        }
+
+        family(&mut out, "rackfan_switches_suppressed_total", "counter",
+               "Fan switches held back by the [cycling] limits.");
+        for zone in &zones {
+            for (reason, count) in &zone.suppressed_switches {
+                let _ = writeln!(out, "rackfan_switches_suppressed_total{{zone=\"{}\",reason=\"{}\"}} {}",
+                                 label(&zone.name), reason, count);
+            }
+        }
+
+        family(&mut out, "rackfan_fan_rpm", "gauge", "Fan speed from the tachometer.");
+        for zone in &zones {
+            if let Some(rpm) = zone.rpm {
+                let _ = writeln!(out, "rackfan_fan_rpm{{zone=\"{}\"}} {}", label(&zone.name), rpm);
+            }
+        }
+
+        family(&mut out, "rackfan_loop_duration_seconds", "gauge",
+               "Time the last control cycle took, sensor reads included.");
+        let _ = writeln!(out, "rackfan_loop_duration_seconds {}", loop_duration.as_secs_f64());
+
+        family(&mut out, "rackfan_cycles_total", "counter", "Control cycles run.");
+        let _ = writeln!(out, "rackfan_cycles_total {}", self.cycles);
+
+        out
+    }
+}
@@ -0,0 +1,525 @@
+//! MQTT publishing: retained sensor readings and zone state, an availability
+//! topic backed by the broker's last will, Home Assistant discovery configs
+//! and a command topic per zone that takes `on`, `off` or `auto`.
+//!
+//! Topics under `base_topic` (default `rackfan`):
+//!
+//! - `status`: `online`/`offline`
+//! - `sensor/<sensor>/temperature`: °C, empty while the sensor fails
+//! - `zone/<zone>/state`: JSON, same fields as `rackfanctl --json fan`
+//! - `zone/<zone>/set` and `set` (all zones): `on`, `off`, `auto` or
+//!   `{"mode": "on", "duration_secs": 1800}`
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Problems;
+use crate::control::{Request, SensorStatus};
+use crate::zone::{Control, ZoneStatus};
+
+/// Pause before reconnecting after the broker drops or refuses us.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// `[mqtt]` table.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub base_topic: Option<String>,
+    /// Prefix Home Assistant watches for discovery; `discovery = false` turns
+    /// the configs off.
+    pub discovery_prefix: Option<String>,
+    pub discovery: Option<bool>,
+    pub keep_alive_secs: Option<u64>,
+}
+
+impl MqttConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(1883)
+    }
+
+    pub fn client_id(&self) -> &str {
+        self.client_id.as_deref().unwrap_or("rackfan")
+    }
+
+    pub fn base_topic(&self) -> &str {
+        self.base_topic.as_deref().unwrap_or("rackfan")
+    }
+
+    pub fn discovery_prefix(&self) -> &str {
+        self.discovery_prefix.as_deref().unwrap_or("homeassistant")
+    }
+
+    pub fn discovery(&self) -> bool {
+        self.discovery.unwrap_or(true)
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_secs.unwrap_or(30))
+    }
+
+    pub fn resolved(&self) -> Self {
+        MqttConfig {
+            port: Some(self.port()),
+            client_id: Some(self.client_id().to_string()),
+            base_topic: Some(self.base_topic().to_string()),
+            discovery_prefix: Some(self.discovery_prefix().to_string()),
+            discovery: Some(self.discovery()),
+            keep_alive_secs: Some(self.keep_alive().as_secs()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.host.is_empty() {
+            problems.push(format_args!("{}.host", path), "must not be empty");
+        }
+        if self.port == Some(0) {
+            problems.push(format_args!("{}.port", path), "must not be 0");
+        }
+        if self.username.is_none() && self.password.is_some() {
+            problems.push(format_args!("{}.password", path), "needs a username");
+        }
+        for (key, topic) in [("base_topic", self.base_topic()),
+                             ("discovery_prefix", self.discovery_prefix())] {
+            if topic.is_empty() || topic.ends_with('/') || topic.contains(['+', '#']) {
+                problems.push(format_args!("{}.{}", path, key),
+                              "must be a topic without wildcards or a trailing '/'");
+            }
+        }
+        if self.keep_alive_secs.is_some_and(|secs| secs < 5) {
+            problems.push(format_args!("{}.keep_alive_secs", path), "must be at least 5");
+        }
+    }
+}
+
+/// Topic-safe form of a sensor or zone name.
+fn slug(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+struct Topics {
+    base: String,
+}
+
+impl Topics {
+    fn status(&self) -> String {
+        format!("{}/status", self.base)
+    }
+
+    fn temperature(&self, sensor: &str) -> String {
+        format!("{}/sensor/{}/temperature", self.base, slug(sensor))
+    }
+
+    fn humidity(&self, sensor: &str) -> String {
+        format!("{}/sensor/{}/humidity", self.base, slug(sensor))
+    }
+
+    fn zone_state(&self, zone: &str) -> String {
+        format!("{}/zone/{}/state", self.base, slug(zone))
+    }
+
+    fn zone_set(&self, zone: &str) -> String {
+        format!("{}/zone/{}/set", self.base, slug(zone))
+    }
+
+    fn set_all(&self) -> String {
+        format!("{}/set", self.base)
+    }
+}
+
+/// Retained Home Assistant discovery configs: a temperature entity per
+/// sensor and, per zone, its temperature, fan state, duty and a mode select.
+fn discovery(config: &MqttConfig, sensors: &[String], zones: &[String]) -> Vec<(String, String)> {
+    let topics = Topics { base: config.base_topic().to_string() };
+    let node = slug(config.client_id());
+    let device = json!({
+        "identifiers": [node],
+        "name": format!("RackFan {}", config.client_id()),
+        "manufacturer": "RackBox",
+        "model": "rackfan_daemon",
+    });
+    let entity = |component: &str, object: String, mut payload: serde_json::Value| {
+        payload["unique_id"] = json!(format!("{}_{}", node, object));
+        payload["object_id"] = payload["unique_id"].clone();
+        payload["availability_topic"] = json!(topics.status());
+        payload["device"] = device.clone();
+        let topic = format!("{}/{}/{}/{}/config", config.discovery_prefix(), component, node, object);
+        (topic, payload.to_string())
+    };
+
+    let mut configs = Vec::new();
+    for sensor in sensors {
+        configs.push(entity("sensor", format!("{}_temperature", slug(sensor)), json!({
+            "name": format!("{} temperature", sensor),
+            "state_topic": topics.temperature(sensor),
+            "device_class": "temperature",
+            "state_class": "measurement",
+            "unit_of_measurement": "°C",
+        })));
+    }
+    for zone in zones {
+        let state = topics.zone_state(zone);
+        configs.push(entity("sensor", format!("{}_temperature", slug(zone)), json!({
+            "name": format!("{} zone temperature", zone),
+            "state_topic": state,
+            "value_template": "{{ value_json.temperature }}",
+            "device_class": "temperature",
+            "state_class": "measurement",
+            "unit_of_measurement": "°C",
+        })));
+        configs.push(entity("binary_sensor", format!("{}_fan", slug(zone)), json!({
+            "name": format!("{} fan", zone),
+            "state_topic": state,
+            "value_template": "{{ 'ON' if value_json.fan_on else 'OFF' }}",
+            "device_class": "running",
+        })));
+        configs.push(entity("sensor", format!("{}_duty", slug(zone)), json!({
+            "name": format!("{} fan duty", zone),
+            "state_topic": state,
+            "value_template": "{{ value_json.duty }}",
+            "unit_of_measurement": "%",
+        })));
+        configs.push(entity("select", format!("{}_control", slug(zone)), json!({
+            "name": format!("{} fan control", zone),
+            "state_topic": state,
+            "value_template": "{{ value_json.control }}",
+            "command_topic": topics.zone_set(zone),
+            "options": ["auto", "on", "off"],
+        })));
+    }
+    configs
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Command {
+    mode: Control,
+    duration_secs: Option<u64>,
+}
+
+/// Control request for a message on one of the command topics.
+fn parse_command(topics: &Topics, zones: &[String], topic: &str, payload: &[u8])
+                 -> Result<Request> {
+    let zone = if topic == topics.set_all() {
+        None
+    } else {
+        let zone = zones.iter().find(|zone| topics.zone_set(zone) == topic)
+            .with_context(|| format!("no zone for topic {}", topic))?;
+        Some(zone.clone())
+    };
+
+    let payload = std::str::from_utf8(payload).context("payload is not UTF-8")?.trim();
+    let command = match payload.to_ascii_lowercase().as_str() {
+        "on" => Command { mode: Control::On, duration_secs: None },
+        "off" => Command { mode: Control::Off, duration_secs: None },
+        "auto" => Command { mode: Control::Auto, duration_secs: None },
+        _ => serde_json::from_str(payload)
+            .with_context(|| format!("invalid command '{}', use on, off, auto or JSON", payload))?,
+    };
+    Ok(Request::Control { zone, mode: command.mode, duration_secs: command.duration_secs })
+}
+
+pub struct MqttLink {
+    client: Client,
+    topics: Topics,
+    commands: Receiver<Request>,
+}
+
+impl MqttLink {
+    /// Starts the client; connecting and reconnecting happen in the
+    /// background, so an unreachable broker never holds up fan control.
+    pub fn connect(config: &MqttConfig, sensors: &[String], zones: &[String]) -> Result<Self> {
+        let topics = Topics { base: config.base_topic().to_string() };
+
+        let mut options = MqttOptions::new(config.client_id(), config.host.clone(), config.port());
+        options.set_keep_alive(config.keep_alive());
+        options.set_last_will(LastWill::new(topics.status(), "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = &config.username {
+            options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+        }
+        let (client, mut connection) = Client::new(options, 64);
+
+        let discovery = if config.discovery() { discovery(config, sensors, zones) } else { Vec::new() };
+        let (tx, commands) = mpsc::channel();
+        let session = Session {
+            client: client.clone(),
+            topics: Topics { base: topics.base.clone() },
+            zones: zones.to_vec(),
+            discovery,
+            commands: tx,
+        };
+        let broker = format!("{}:{}", config.host, config.port());
+        info!("MQTT publishing to {} under {}/", broker, topics.base);
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("MQTT connected to {}", broker);
+                        session.start();
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        session.command(&publish.topic, &publish.payload);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection to {} failed: {}", broker, e);
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+
+        Ok(MqttLink { client, topics, commands })
+    }
+
+    /// Commands received since the last call.
+    pub fn commands(&self) -> Vec<Request> {
+        self.commands.try_iter().collect()
+    }
+
+    /// Retained state after a cycle or a command.
+    pub fn publish_state(&self, sensors: &[SensorStatus], zones: &[ZoneStatus]) {
+        for sensor in sensors {
+            let payload = sensor.temperature.map_or(String::new(), |t| format!("{:.2}", t));
+            self.publish(self.topics.temperature(&sensor.name), payload);
+            if let Some(humidity) = sensor.humidity {
+                self.publish(self.topics.humidity(&sensor.name), format!("{:.1}", humidity));
+            }
+        }
+        for zone in zones {
+            match serde_json::to_string(zone) {
+                Ok(payload) => self.publish(self.topics.zone_state(&zone.name), payload),
+                Err(e) => warn!("MQTT state of zone '{}': {}", zone.name, e),
+            }
+        }
+    }
+
+    fn publish(&self, topic: String, payload: String) {
+        // Never block the control loop: drop updates while the queue is full.
+        if let Err(e) = self.client.try_publish(topic, QoS::AtMostOnce, true, payload) {
+            debug!("MQTT update dropped: {}", e);
+        }
+    }
+
+    /// Marks the daemon offline and disconnects cleanly, so the broker does
+    /// not have to fall back to the last will.
+    pub fn close(self) {
+        let _ = self.client.try_publish(self.topics.status(), QoS::AtLeastOnce, true, "offline");
+        let _ = self.client.try_disconnect();
+    }
+}
+
+/// Connection-thread side of the link.
+struct Session {
+    client: Client,
+    topics: Topics,
+    zones: Vec<String>,
+    discovery: Vec<(String, String)>,
+    commands: Sender<Request>,
+}
+
+impl Session {
+    /// Runs on every (re)connection: the session is clean, so subscriptions
+    /// and the online marker are sent again each time.
+    fn start(&self) {
+        let mut topics = vec![self.topics.set_all()];
+        topics.extend(self.zones.iter().map(|zone| self.topics.zone_set(zone)));
+        for topic in topics {
+            if let Err(e) = self.client.try_subscribe(topic, QoS::AtLeastOnce) {
+                warn!("MQTT subscribe failed: {}", e);
+            }
+        }
+        for (topic, payload) in &self.discovery {
+            let _ = self.client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_str());
+        }
+        let _ = self.client.try_publish(self.topics.status(), QoS::AtLeastOnce, true, "online");
+    }
+
+    fn command(&self, topic: &str, payload: &[u8]) {
+        match parse_command(&self.topics, &self.zones, topic, payload) {
+            Ok(request) => {
+                info!("MQTT command on {}: {:?}", topic, request);
+                let _ = self.commands.send(request);
+            }
+            Err(e) => warn!("MQTT command on {} ignored: {:#}", topic, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn config() -> MqttConfig {
+        toml::from_str("host = \"127.0.0.1\"\nclient_id = \"rack 1\"").unwrap()
+    }
+
+    #[test]
+    fn commands_map_to_control_requests() {
+        let topics = Topics { base: "rackfan".to_string() };
+        let zones = vec!["Top Rack".to_string()];
+
+        let request = parse_command(&topics, &zones, "rackfan/zone/top_rack/set", b"ON").unwrap();
+        assert!(matches!(request,
+            Request::Control { zone: Some(z), mode: Control::On, duration_secs: None } if z == "Top Rack"));
+
+        let request = parse_command(&topics, &zones, "rackfan/set",
+                                    br#"{"mode": "off", "duration_secs": 600}"#).unwrap();
+        assert!(matches!(request,
+            Request::Control { zone: None, mode: Control::Off, duration_secs: Some(600) }));
+
+        assert!(parse_command(&topics, &zones, "rackfan/zone/gpu/set", b"on").is_err());
+        assert!(parse_command(&topics, &zones, "rackfan/set", b"max").is_err());
+    }
+
+    #[test]
+    fn discovery_describes_sensors_and_zones() {
+        let configs = discovery(&config(), &["inlet".to_string()], &["top".to_string()]);
+        let topics: Vec<&str> = configs.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(topics, vec![
+            "homeassistant/sensor/rack_1/inlet_temperature/config",
+            "homeassistant/sensor/rack_1/top_temperature/config",
+            "homeassistant/binary_sensor/rack_1/top_fan/config",
+            "homeassistant/sensor/rack_1/top_duty/config",
+            "homeassistant/select/rack_1/top_control/config",
+        ]);
+
+        let select: serde_json::Value = serde_json::from_str(&configs[4].1).unwrap();
+        assert_eq!(select["command_topic"], "rackfan/zone/top/set");
+        assert_eq!(select["state_topic"], "rackfan/zone/top/state");
+        assert_eq!(select["availability_topic"], "rackfan/status");
+        assert_eq!(select["unique_id"], "rack_1_top_control");
+    }
+
+    type Messages = Vec<(String, String)>;
+
+    /// Reads one MQTT packet: first header byte and body.
+    fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).ok()?;
+        let kind = byte[0];
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            stream.read_exact(&mut byte).ok()?;
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((kind, body))
+    }
+
+    /// Just enough of a broker to accept one client, record what it publishes
+    /// and push a command back on the first subscription.
+    fn fake_broker(listener: TcpListener, published: Sender<(String, String)>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut commanded = false;
+        while let Some((kind, body)) = read_packet(&mut stream) {
+            match kind >> 4 {
+                // CONNECT: report the will, then accept.
+                1 => {
+                    let will = String::from_utf8_lossy(&body);
+                    if will.contains("rackfan/status") && will.contains("offline") {
+                        let _ = published.send(("will".to_string(), "offline".to_string()));
+                    }
+                    stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+                }
+                // PUBLISH
+                3 => {
+                    let topic_len = usize::from(body[0]) << 8 | usize::from(body[1]);
+                    let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+                    let mut payload = 2 + topic_len;
+                    if (kind >> 1) & 3 > 0 {
+                        stream.write_all(&[0x40, 2, body[payload], body[payload + 1]]).unwrap();
+                        payload += 2;
+                    }
+                    let payload = String::from_utf8_lossy(&body[payload..]).into_owned();
+                    let _ = published.send((topic, payload));
+                }
+                // SUBSCRIBE: acknowledge, then send a command.
+                8 => {
+                    let topics = (body.len() - 2) / 3;
+                    let mut ack = vec![0x90, 2 + topics as u8, body[0], body[1]];
+                    ack.extend(std::iter::repeat_n(1, topics));
+                    stream.write_all(&ack).unwrap();
+                    if std::mem::replace(&mut commanded, true) {
+                        continue;
+                    }
+
+                    let topic = b"rackfan/zone/top/set";
+                    let mut command = vec![0x30, (2 + topic.len() + 2) as u8, 0, topic.len() as u8];
+                    command.extend(topic);
+                    command.extend(b"on");
+                    stream.write_all(&command).unwrap();
+                }
+                // PINGREQ
+                12 => stream.write_all(&[0xd0, 0]).unwrap(),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn link_announces_itself_publishes_state_and_takes_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = config();
+        config.port = Some(listener.local_addr().unwrap().port());
+        let (tx, published) = mpsc::channel();
+        thread::spawn(move || fake_broker(listener, tx));
+
+        let link = MqttLink::connect(&config, &["inlet".to_string()], &["top".to_string()]).unwrap();
+        let mut seen = Vec::new();
+        let receive_until = |seen: &mut Messages, done: &dyn Fn(&Messages) -> bool| {
+            while !done(seen) {
+                seen.push(published.recv_timeout(Duration::from_secs(5)).expect("broker timed out"));
+            }
+        };
+        receive_until(&mut seen, &|seen| seen.iter().any(|(t, p)| t == "rackfan/status" && p == "online"));
+        assert!(seen.contains(&("will".to_string(), "offline".to_string())));
+        assert!(seen.iter().any(|(t, _)| t == "homeassistant/select/rack_1/top_control/config"));
+
+        let sensor = SensorStatus {
+            name: "inlet".to_string(),
+            temperature: Some(24.5),
+            humidity: None,
+            pressure: None,
+            dew_point: None,
+            error: None,
+        };
+        link.publish_state(&[sensor], &[]);
+        receive_until(&mut seen, &|seen| seen.iter().any(|(t, _)| t == "rackfan/sensor/inlet/temperature"));
+        assert!(seen.contains(&("rackfan/sensor/inlet/temperature".to_string(), "24.50".to_string())));
+
+        let mut commands = Vec::new();
+        for _ in 0..50 {
+            commands.extend(link.commands());
+            if !commands.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(matches!(commands.as_slice(),
+            [Request::Control { zone: Some(z), mode: Control::On, .. }] if z == "top"));
+
+        link.close();
+        receive_until(&mut seen, &|seen| seen.last().is_some_and(|(t, p)| t == "rackfan/status" && p == "offline"));
+    }
+}
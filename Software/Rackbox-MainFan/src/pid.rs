@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Problems;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PidConfig {
+    /// Target temperature in °C.
+    pub setpoint: f32,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Output clamp in duty percent. Defaults to 0-100.
+    pub output_min: Option<f32>,
+    pub output_max: Option<f32>,
+}
+
+impl PidConfig {
+    pub fn output_limits(&self) -> (f32, f32) {
+        (self.output_min.unwrap_or(0.0), self.output_max.unwrap_or(100.0))
+    }
+
+    pub fn resolved(&self) -> Self {
+        let (min, max) = self.output_limits();
+        PidConfig { output_min: Some(min), output_max: Some(max), ..self.clone() }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        let fields = [("setpoint", self.setpoint), ("kp", self.kp), ("ki", self.ki), ("kd", self.kd)];
+        for (field, value) in fields {
+            if !value.is_finite() {
+                problems.push(format_args!("{}.{}", path, field), "must be a finite number");
+            }
+        }
+
+        let (min, max) = self.output_limits();
+        if !(0.0..=100.0).contains(&min) {
+            problems.push(format_args!("{}.output_min", path),
+                          format_args!("must be between 0 and 100 (got {})", min));
+        }
+        if !(0.0..=100.0).contains(&max) {
+            problems.push(format_args!("{}.output_max", path),
+                          format_args!("must be between 0 and 100 (got {})", max));
+        }
+        if min >= max {
+            problems.push(format_args!("{}.output_min", path),
+                          format_args!("must be lower than output_max ({} >= {})", min, max));
+        }
+    }
+}
+
+/// PID controller for a cooling loop: the error is `measurement - setpoint`,
+/// so a rack hotter than the setpoint asks for more fan.
+///
+/// The derivative term acts on the measurement rather than the error, which
+/// avoids a kick when the setpoint changes. The integral only accumulates
+/// while the output is not saturated in the direction of the error
+/// (conditional integration), and is bounded to the output range.
+pub struct PidController {
+    config: PidConfig,
+    integral: f32,
+    last_measurement: Option<f32>,
+    /// Upper output bound below `output_max`, e.g. a profile's `max_duty`.
+    ceiling: f32,
+}
+
+impl PidController {
+    pub fn new(config: PidConfig) -> Self {
+        PidController {
+            config,
+            integral: 0.0,
+            last_measurement: None,
+            ceiling: 100.0,
+        }
+    }
+
+    pub fn setpoint(&self) -> f32 {
+        self.config.setpoint
+    }
+
+    /// Replaces gains, setpoint and limits while keeping the accumulated state.
+    pub fn set_config(&mut self, config: PidConfig) {
+        self.config = config;
+        let (min, max) = self.config.output_limits();
+        self.integral = self.integral.clamp(min, max);
+    }
+
+    /// Caps the output below `output_max` from the next update on. The
+    /// integral is bounded by the cap too, so it doesn't wind up against it.
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = ceiling;
+    }
+
+    fn output_limits(&self) -> (f32, f32) {
+        let (min, max) = self.config.output_limits();
+        (min.min(self.ceiling), max.min(self.ceiling))
+    }
+
+    /// Advances the controller by `dt` seconds and returns the clamped output.
+    pub fn update(&mut self, measurement: f32, dt: f32) -> f32 {
+        let (min, max) = self.output_limits();
+        let error = measurement - self.config.setpoint;
+
+        let derivative = match self.last_measurement {
+            Some(last) if dt > 0.0 => (measurement - last) / dt,
+            _ => 0.0,
+        };
+        self.last_measurement = Some(measurement);
+
+        let p = self.config.kp * error;
+        let d = self.config.kd * derivative;
+        let unclamped = p + self.integral + d;
+
+        let saturated_high = unclamped >= max && error > 0.0;
+        let saturated_low = unclamped <= min && error < 0.0;
+        if dt > 0.0 && !saturated_high && !saturated_low {
+            self.integral = (self.integral + self.config.ki * error * dt).clamp(min, max);
+        }
+
+        (p + self.integral + d).clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(kp: f32, ki: f32, kd: f32) -> PidConfig {
+        PidConfig {
+            setpoint: 30.0,
+            kp,
+            ki,
+            kd,
+            output_min: None,
+            output_max: None,
+        }
+    }
+
+    #[test]
+    fn proportional_only() {
+        let mut pid = PidController::new(config(10.0, 0.0, 0.0));
+        assert_eq!(pid.update(32.0, 1.0), 20.0);
+        assert_eq!(pid.update(30.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn output_is_clamped() {
+        let mut pid = PidController::new(PidConfig {
+            output_min: Some(10.0),
+            output_max: Some(80.0),
+            ..config(50.0, 0.0, 0.0)
+        });
+        assert_eq!(pid.update(40.0, 1.0), 80.0);
+        assert_eq!(pid.update(20.0, 1.0), 10.0);
+    }
+
+    #[test]
+    fn integral_accumulates_error() {
+        let mut pid = PidController::new(config(0.0, 1.0, 0.0));
+        pid.update(32.0, 1.0);
+        pid.update(32.0, 1.0);
+        assert_eq!(pid.update(32.0, 1.0), 6.0);
+    }
+
+    #[test]
+    fn anti_windup_recovers_quickly() {
+        let mut pid = PidController::new(config(10.0, 1.0, 0.0));
+        // Long stretch far above the setpoint: output pinned at 100%.
+        for _ in 0..1000 {
+            assert_eq!(pid.update(45.0, 1.0), 100.0);
+        }
+        // Without anti-windup the integral would be ~15000 and the fan would
+        // stay at 100% long after the rack cooled down.
+        let out = pid.update(28.0, 1.0);
+        assert!(out < 100.0, "output still saturated: {}", out);
+        assert!(pid.integral <= 100.0);
+    }
+
+    #[test]
+    fn ceiling_limits_the_integral_too() {
+        let mut pid = PidController::new(config(10.0, 1.0, 0.0));
+        // Quiet-hours cap of 50% while the rack is far too hot.
+        pid.set_ceiling(50.0);
+        for _ in 0..1000 {
+            assert_eq!(pid.update(45.0, 1.0), 50.0);
+        }
+        assert!(pid.integral <= 50.0);
+        // Just below the setpoint the output drops below the cap at once.
+        let out = pid.update(29.0, 1.0);
+        assert!(out < 50.0, "output still at the ceiling: {}", out);
+    }
+
+    #[test]
+    fn derivative_acts_on_measurement() {
+        let mut pid = PidController::new(config(0.0, 0.0, 5.0));
+        assert_eq!(pid.update(30.0, 1.0), 0.0);
+        // Rising 2°C/s -> 10% from the derivative term.
+        assert_eq!(pid.update(32.0, 1.0), 10.0);
+        // Steady temperature, however far from the setpoint: no derivative.
+        assert_eq!(pid.update(32.0, 1.0), 0.0);
+        // Changing the setpoint must not produce a derivative kick.
+        pid.set_config(PidConfig { setpoint: 25.0, ..config(0.0, 0.0, 5.0) });
+        assert_eq!(pid.update(32.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn set_config_keeps_integral_within_new_limits() {
+        let mut pid = PidController::new(config(0.0, 10.0, 0.0));
+        for _ in 0..10 {
+            pid.update(40.0, 1.0);
+        }
+        pid.set_config(PidConfig { output_max: Some(60.0), ..config(0.0, 10.0, 0.0) });
+        assert_eq!(pid.update(30.0, 1.0), 60.0);
+    }
+
+    #[test]
+    fn settles_on_simulated_rack() {
+        // First-order plant: a heat source pushes the rack towards 40°C and the
+        // fan pulls it towards the 20°C ambient in proportion to duty.
+        let mut pid = PidController::new(config(8.0, 0.5, 0.0));
+        let mut temp = 35.0_f32;
+        for _ in 0..600 {
+            let duty = pid.update(temp, 1.0);
+            temp += 0.05 * (40.0 - temp) - 0.002 * duty * (temp - 20.0);
+        }
+        assert!((temp - 30.0).abs() < 0.2, "rack settled at {}", temp);
+    }
+}
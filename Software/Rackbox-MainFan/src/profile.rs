@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::Problems;
+use crate::zone::ZoneSettings;
+
+/// `[[profile]]` entry: settings that replace the zone's own during a daily
+/// time window, e.g. warmer thresholds and a duty cap for quiet nights.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    /// Local time `HH:MM` the profile starts; `end` may be on the next day.
+    pub start: String,
+    pub end: String,
+    /// Zones the profile applies to; all zones when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+    pub temp_minima: Option<f32>,
+    pub temp_maxima: Option<f32>,
+    /// Cap on the PWM/PID duty in percent; on/off fans are not affected.
+    pub max_duty: Option<f32>,
+    /// Control temperature at which the profile is ignored until it drops
+    /// again. Defaults to 5°C above the profile's `temp_maxima`.
+    pub critical_temp: Option<f32>,
+}
+
+/// Minutes after midnight for `HH:MM`.
+fn parse_clock(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    if minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+impl ProfileConfig {
+    /// Whether `minute` (after local midnight) is inside the window. A window
+    /// with equal start and end lasts all day.
+    pub fn covers(&self, minute: u32) -> bool {
+        let (Some(start), Some(end)) = (parse_clock(&self.start), parse_clock(&self.end)) else {
+            return false;
+        };
+        match start.cmp(&end) {
+            std::cmp::Ordering::Less => (start..end).contains(&minute),
+            std::cmp::Ordering::Greater => minute >= start || minute < end,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+
+    pub fn applies_to(&self, zone: &str) -> bool {
+        self.zones.is_empty() || self.zones.iter().any(|z| z == zone)
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.name.trim().is_empty() {
+            problems.push(format_args!("{}.name", path), "must not be empty");
+        }
+        for (key, value) in [("start", &self.start), ("end", &self.end)] {
+            if parse_clock(value).is_none() {
+                problems.push(format_args!("{}.{}", path, key),
+                              format_args!("'{}' is not a time like 23:00", value));
+            }
+        }
+        if self.max_duty.is_some_and(|d| !(0.0..=100.0).contains(&d)) {
+            problems.push(format_args!("{}.max_duty", path), "must be between 0 and 100");
+        }
+        if self.critical_temp.is_some_and(|t| !t.is_finite()) {
+            problems.push(format_args!("{}.critical_temp", path), "must be a finite number");
+        }
+    }
+
+    /// Checks the profile against the zones it applies to.
+    pub fn validate_zones(&self, path: &str, zones: &[ZoneSettings], problems: &mut Problems) {
+        for name in &self.zones {
+            if !zones.iter().any(|zone| &zone.name == name) {
+                problems.push(format_args!("{}.zones", path), format_args!("no zone named '{}'", name));
+            }
+        }
+        for zone in zones.iter().filter(|zone| self.applies_to(&zone.name)) {
+            let limits = self.limits(zone);
+            if limits.temp_minima >= limits.temp_maxima {
+                problems.push(path, format_args!(
+                    "in zone '{}' temp_minima ({}) must be lower than temp_maxima ({})",
+                    zone.name, limits.temp_minima, limits.temp_maxima));
+            }
+        }
+    }
+
+    /// The zone's limits while the profile is active.
+    pub fn limits(&self, zone: &ZoneSettings) -> Limits {
+        Limits {
+            temp_minima: self.temp_minima.unwrap_or(zone.temp_minima),
+            temp_maxima: self.temp_maxima.unwrap_or(zone.temp_maxima),
+            max_duty: self.max_duty.unwrap_or(100.0),
+        }
+    }
+
+    pub fn critical_temp(&self, zone: &ZoneSettings) -> f32 {
+        self.critical_temp.unwrap_or(self.limits(zone).temp_maxima + 5.0)
+    }
+}
+
+impl fmt::Display for ProfileConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' ({}-{})", self.name, self.start, self.end)
+    }
+}
+
+/// Thresholds and duty cap a zone controls with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub temp_minima: f32,
+    pub temp_maxima: f32,
+    pub max_duty: f32,
+}
+
+/// First profile covering `minute` for `zone`.
+pub fn active<'a>(profiles: &'a [ProfileConfig], zone: &str, minute: u32)
+                  -> Option<&'a ProfileConfig> {
+    profiles.iter().find(|p| p.applies_to(zone) && p.covers(minute))
+}
+
+/// Minutes since local midnight.
+pub fn local_minute() -> u32 {
+    // SAFETY: localtime_r only writes the tm we pass.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(start: &str, end: &str) -> ProfileConfig {
+        ProfileConfig {
+            name: "night".to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+            zones: Vec::new(),
+            temp_minima: None,
+            temp_maxima: None,
+            max_duty: None,
+            critical_temp: None,
+        }
+    }
+
+    #[test]
+    fn windows_may_cross_midnight() {
+        let night = profile("23:00", "07:00");
+        assert!(night.covers(23 * 60));
+        assert!(night.covers(2 * 60));
+        assert!(!night.covers(7 * 60));
+        assert!(!night.covers(12 * 60));
+
+        let lunch = profile("12:00", "13:30");
+        assert!(lunch.covers(13 * 60));
+        assert!(!lunch.covers(13 * 60 + 30));
+
+        assert!(profile("00:00", "00:00").covers(17 * 60));
+        assert!(!profile("24:00", "07:00").covers(2 * 60));
+        assert_eq!(parse_clock("7:05"), Some(425));
+        assert_eq!(parse_clock("07:5x"), None);
+    }
+}
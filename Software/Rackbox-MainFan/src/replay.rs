@@ -0,0 +1,202 @@
+//! `rackfan_daemon replay <csv>`: runs recorded temperatures through the
+//! control logic with simulated fans, to tune thresholds offline.
+//!
+//! The CSV has a header row `timestamp,<sensor>,<sensor>...` with timestamps
+//! in seconds (epoch or relative). An empty cell or `error` is replayed as a
+//! failed sensor read. Without a header a single column of temperatures
+//! after the timestamp is read as sensor `sensor`.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::fan::{FanController, FanMode};
+use crate::hook::HooksConfig;
+use crate::sensor::{Sensor, SensorSet};
+use crate::sim::{RecordingFan, ScriptedSensor, SensorScript};
+use crate::zone::Zone;
+use crate::{Daemon, DEFAULT_CONFIG_PATH};
+
+const USAGE: &str = "usage: rackfan_daemon replay <csv> [--config <path>] [--output <csv>]";
+
+struct Sample {
+    time: f64,
+    temps: Vec<Option<f32>>,
+}
+
+#[derive(Default)]
+struct ZoneStats {
+    switches: u32,
+    on_secs: f64,
+    duty_secs: f64,
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let mut csv_path = None;
+    let mut config_path = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().context(USAGE)?),
+            "--output" => output = Some(args.next().context(USAGE)?),
+            path if csv_path.is_none() => csv_path = Some(path),
+            other => return Err(anyhow::anyhow!("unexpected argument '{}'\n{}", other, USAGE)),
+        }
+    }
+    let csv_path = csv_path.context(USAGE)?;
+
+    // An explicit --config must be valid; the default path is optional.
+    let config = match config_path {
+        Some(path) => Config::load(path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::load(DEFAULT_CONFIG_PATH)?,
+        None => Config::default(),
+    };
+
+    let content = fs::read_to_string(csv_path)
+        .with_context(|| format!("Failed to read {}", csv_path))?;
+    let (names, samples) = parse_csv(&content)?;
+
+    let timeline = replay(config, &names, &samples)?;
+    if let Some(path) = output {
+        fs::write(path, timeline).with_context(|| format!("Failed to write {}", path))?;
+        println!("Timeline written to {}", path);
+    }
+    Ok(())
+}
+
+fn parse_csv(content: &str) -> Result<(Vec<String>, Vec<Sample>)> {
+    let mut lines = content.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    let mut names = vec!["sensor".to_string()];
+    if let Some((_, header)) = lines.peek()
+        && header.split(',').next().is_some_and(|t| t.trim().parse::<f64>().is_err())
+    {
+        names = header.split(',').skip(1).map(|n| n.trim().to_string()).collect();
+        lines.next();
+    }
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("CSV header has no sensor columns"));
+    }
+
+    let mut samples: Vec<Sample> = Vec::new();
+    for (line_no, line) in lines {
+        let mut fields = line.split(',').map(str::trim);
+        let time: f64 = fields.next()
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| format!("line {}: invalid timestamp", line_no))?;
+        if samples.last().is_some_and(|last| time < last.time) {
+            return Err(anyhow::anyhow!("line {}: timestamp goes backwards", line_no));
+        }
+
+        let temps = fields
+            .map(|field| match field {
+                "" | "error" => Ok(None),
+                value => value.parse::<f32>().map(Some)
+                    .with_context(|| format!("line {}: invalid temperature '{}'", line_no, value)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if temps.len() != names.len() {
+            return Err(anyhow::anyhow!("line {}: expected {} temperatures, got {}",
+                                       line_no, names.len(), temps.len()));
+        }
+
+        samples.push(Sample { time, temps });
+    }
+
+    if samples.is_empty() {
+        return Err(anyhow::anyhow!("CSV contains no samples"));
+    }
+    Ok((names, samples))
+}
+
+/// Replays the samples, prints state changes and a summary per zone, and
+/// returns the full timeline as CSV.
+fn replay(mut config: Config, names: &[String], samples: &[Sample]) -> Result<String> {
+    // A simulation must not run hooks or alarm commands.
+    config.hooks = HooksConfig::default();
+
+    let mut scripts: Vec<SensorScript> = Vec::new();
+    let mut sensors: Vec<Box<dyn Sensor>> = Vec::new();
+    for name in names {
+        let (sensor, script) = ScriptedSensor::new(name);
+        sensors.push(Box::new(sensor));
+        scripts.push(script);
+    }
+    let sensors = SensorSet::from_sensors(sensors).with_filter(&config.filter);
+
+    let settings = config.zones()?;
+    Daemon::check_zone_sensors(&sensors, &settings)?;
+    let zones: Vec<Zone> = settings.into_iter()
+        .map(|mut settings| {
+            settings.failsafe.lost_command = None;
+            settings.alarm.warning_commands.clear();
+            settings.alarm.critical_commands.clear();
+            let (driver, _) = RecordingFan::new(settings.fan_mode != FanMode::OnOff);
+            let fan = FanController::with_driver(settings.name.clone(), Box::new(driver));
+            Zone::with_fan(settings, fan)
+        })
+        .collect();
+
+    let interval = config.check_interval_secs() as f64;
+    let mut daemon = Daemon::with_parts("replay".to_string(), config, sensors, zones);
+    let mut stats: Vec<ZoneStats> = daemon.zones.iter().map(|_| ZoneStats::default()).collect();
+    let mut duties = vec![0.0_f32; daemon.zones.len()];
+
+    let mut timeline = String::from("timestamp,zone,duty\n");
+    println!("{:>12}  {:<12} {:>6}", "time", "zone", "duty");
+
+    for (i, sample) in samples.iter().enumerate() {
+        for (script, temp) in scripts.iter().zip(&sample.temps) {
+            match temp {
+                Some(temp) => script.push(*temp),
+                None => script.push_error("recorded read error"),
+            }
+        }
+
+        let dt = match i {
+            0 => interval,
+            _ => sample.time - samples[i - 1].time,
+        };
+        daemon.check_temperature_after(dt as f32)?;
+
+        // Each state holds until the next sample.
+        let held = samples.get(i + 1).map_or(0.0, |next| next.time - sample.time);
+
+        for ((zone, stats), previous) in daemon.zones.iter().zip(&mut stats).zip(&mut duties) {
+            let duty = zone.duty();
+            if (duty > 0.0) != (*previous > 0.0) {
+                stats.switches += 1;
+            }
+            if i == 0 || duty != *previous {
+                println!("{:>12.1}  {:<12} {:>5.0}%", sample.time, zone.settings.name, duty);
+            }
+            if duty > 0.0 {
+                stats.on_secs += held;
+            }
+            stats.duty_secs += held * f64::from(duty) / 100.0;
+            *previous = duty;
+
+            writeln!(timeline, "{},{},{:.1}", sample.time, zone.settings.name, duty)?;
+        }
+    }
+
+    let span = samples[samples.len() - 1].time - samples[0].time;
+    println!("\n{} samples over {:.0}s", samples.len(), span);
+    for (zone, stats) in daemon.zones.iter().zip(&stats) {
+        let percent = |secs: f64| if span > 0.0 { 100.0 * secs / span } else { 0.0 };
+        println!("zone '{}': {} switch events, on {:.1}% of the time, average duty {:.1}%",
+                 zone.settings.name, stats.switches,
+                 percent(stats.on_secs), percent(stats.duty_secs));
+    }
+
+    Ok(timeline)
+}
@@ -0,0 +1,692 @@
+use anyhow::{Context, Result};
+use glob::glob;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::Problems;
+use crate::filter::{FilterConfig, FilteredSensor};
+use crate::i2c::{I2cSensor, I2cSensorConfig};
+use crate::sysfs::{SysfsSensor, SysfsSensorConfig};
+
+const W1_DEVICES: &str = "/sys/bus/w1/devices";
+
+/// `[[sensor]]` entry in config.toml naming a 1-Wire probe by ROM id.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SensorConfig {
+    pub id: String,
+    pub name: String,
+    pub location: Option<String>,
+    /// Conversion resolution in bits, overriding `[onewire] resolution`.
+    pub resolution: Option<u8>,
+}
+
+/// How the readings of all sensors are reduced to the single control input.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(try_from = "RawSensorPolicy", into = "RawSensorPolicy")]
+pub enum SensorPolicy {
+    /// Hottest sensor.
+    #[default]
+    Max,
+    /// Mean of all sensors that could be read.
+    Average,
+    /// One named sensor.
+    Sensor { name: String },
+    /// `hot - cold`, e.g. exhaust minus inlet. Thresholds are then deltas.
+    Delta { hot: String, cold: String },
+}
+
+/// `[sensor_policy]` table as written; serde's tagged enums can't reject
+/// unknown keys on unit variants, so the fields are checked by hand.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawSensorPolicy {
+    kind: String,
+    name: Option<String>,
+    hot: Option<String>,
+    cold: Option<String>,
+}
+
+impl From<SensorPolicy> for RawSensorPolicy {
+    fn from(policy: SensorPolicy) -> Self {
+        let raw = |kind: &str| RawSensorPolicy {
+            kind: kind.to_string(),
+            name: None,
+            hot: None,
+            cold: None,
+        };
+        match policy {
+            SensorPolicy::Max => raw("max"),
+            SensorPolicy::Average => raw("average"),
+            SensorPolicy::Sensor { name } => RawSensorPolicy { name: Some(name), ..raw("sensor") },
+            SensorPolicy::Delta { hot, cold } => {
+                RawSensorPolicy { hot: Some(hot), cold: Some(cold), ..raw("delta") }
+            }
+        }
+    }
+}
+
+impl TryFrom<RawSensorPolicy> for SensorPolicy {
+    type Error = String;
+
+    fn try_from(raw: RawSensorPolicy) -> Result<Self, Self::Error> {
+        let policy = match (raw.kind.as_str(), raw.name, raw.hot, raw.cold) {
+            ("max", None, None, None) => SensorPolicy::Max,
+            ("average", None, None, None) => SensorPolicy::Average,
+            ("sensor", Some(name), None, None) => SensorPolicy::Sensor { name },
+            ("delta", None, Some(hot), Some(cold)) => SensorPolicy::Delta { hot, cold },
+            ("max" | "average", ..) => {
+                return Err(format!("kind = \"{}\" takes no other keys", raw.kind));
+            }
+            ("sensor", ..) => return Err("kind = \"sensor\" requires only `name`".to_string()),
+            ("delta", ..) => {
+                return Err("kind = \"delta\" requires only `hot` and `cold`".to_string());
+            }
+            (kind, ..) => {
+                return Err(format!(
+                    "unknown kind `{}`, expected one of `max`, `average`, `sensor`, `delta`",
+                    kind));
+            }
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for SensorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SensorPolicy::Max => write!(f, "max"),
+            SensorPolicy::Average => write!(f, "average"),
+            SensorPolicy::Sensor { name } => write!(f, "sensor {}", name),
+            SensorPolicy::Delta { hot, cold } => write!(f, "delta {} - {}", hot, cold),
+        }
+    }
+}
+
+impl SensorPolicy {
+    /// Names of sensors the policy depends on.
+    pub fn required_sensors(&self) -> Vec<&str> {
+        match self {
+            SensorPolicy::Max | SensorPolicy::Average => Vec::new(),
+            SensorPolicy::Sensor { name } => vec![name],
+            SensorPolicy::Delta { hot, cold } => vec![hot, cold],
+        }
+    }
+
+    pub fn apply(&self, readings: &[&SensorReading]) -> Result<f32> {
+        let find = |name: &str| -> Result<f32> {
+            let reading = readings.iter()
+                .find(|r| r.name == name)
+                .with_context(|| format!("Sensor '{}' not found", name))?;
+            match &reading.temp {
+                Ok(temp) => Ok(*temp),
+                Err(e) => Err(anyhow::anyhow!("Sensor '{}': {}", name, e)),
+            }
+        };
+
+        let valid: Vec<f32> = readings.iter()
+            .filter_map(|r| r.temp.as_ref().ok().copied())
+            .collect();
+
+        match self {
+            SensorPolicy::Max => valid.into_iter()
+                .reduce(f32::max)
+                .context("No sensor could be read"),
+            SensorPolicy::Average => {
+                if valid.is_empty() {
+                    return Err(anyhow::anyhow!("No sensor could be read"));
+                }
+                Ok(valid.iter().sum::<f32>() / valid.len() as f32)
+            }
+            SensorPolicy::Sensor { name } => find(name),
+            SensorPolicy::Delta { hot, cold } => Ok(find(hot)? - find(cold)?),
+        }
+    }
+}
+
+pub struct SensorReading {
+    pub name: String,
+    pub temp: Result<f32>,
+    pub environment: Environment,
+}
+
+/// Humidity (%) and pressure (hPa) measured along with the temperature by
+/// environmental sensors.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Environment {
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+}
+
+/// Source of temperature readings: a 1-Wire probe, an I2C environmental
+/// sensor or a simulation.
+pub trait Sensor: Send {
+    fn name(&self) -> &str;
+    fn read_temperature(&mut self) -> Result<f32>;
+
+    /// Humidity and pressure of the last reading, if the sensor has them.
+    fn environment(&self) -> Environment {
+        Environment::default()
+    }
+
+    /// Measuring range of the chip; `[filter]` rejects readings outside it.
+    fn range(&self) -> Option<(f32, f32)> {
+        None
+    }
+
+    /// Whether the chip reports failures as temperatures, like the DS18B20's
+    /// 85°C and -127°C; only then does `[filter] reject_values` apply.
+    fn has_error_codes(&self) -> bool {
+        false
+    }
+}
+
+/// Measuring range of the w1_therm probes.
+pub const W1_RANGE: (f32, f32) = (-55.0, 125.0);
+
+/// 1-Wire families handled by the kernel's w1_therm driver.
+const W1_FAMILIES: &[(&str, &str)] = &[
+    ("10", "DS18S20"),
+    ("22", "DS1822"),
+    ("28", "DS18B20"),
+    ("42", "DS28EA00"),
+];
+
+/// Chip name of a ROM id, e.g. `DS18B20` for `28-0316a2794fff`.
+fn family(id: &str) -> Option<&'static str> {
+    let (code, _) = id.split_once('-')?;
+    W1_FAMILIES.iter().find(|(family, _)| *family == code).map(|(_, chip)| *chip)
+}
+
+/// `[onewire]` table: settings for every probe on the 1-Wire bus.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OneWireConfig {
+    /// Conversion resolution in bits (9-12) written to each probe at start;
+    /// `[[sensor]]` entries may override it. Left as is when unset.
+    pub resolution: Option<u8>,
+    /// How often the bus is checked for hot-plugged probes; 0 disables it.
+    pub rescan_secs: Option<u64>,
+}
+
+impl OneWireConfig {
+    pub fn rescan(&self) -> Duration {
+        Duration::from_secs(self.rescan_secs.unwrap_or(60))
+    }
+
+    /// The resolution stays unset: each probe then keeps its own.
+    pub fn resolved(&self) -> Self {
+        OneWireConfig { rescan_secs: Some(self.rescan().as_secs()), ..self.clone() }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        check_resolution(problems, format_args!("{}.resolution", path), self.resolution);
+    }
+}
+
+/// Worst-case conversion time of a w1_therm probe: 750 ms at 12 bits, halved
+/// for every bit less.
+pub fn conversion_time(resolution: Option<u8>) -> Duration {
+    let bits = resolution.unwrap_or(12).clamp(9, 12);
+    Duration::from_millis(750 >> (12 - bits))
+}
+
+pub fn check_resolution(problems: &mut Problems, field: impl Display, resolution: Option<u8>) {
+    if resolution.is_some_and(|bits| !(9..=12).contains(&bits)) {
+        problems.push(field, "must be between 9 and 12 bits");
+    }
+}
+
+/// 1-Wire probe read through the kernel's w1_therm sysfs files.
+pub struct TemperatureSensor {
+    pub name: String,
+    pub location: Option<String>,
+    device_file: String,
+    /// `temperature` attribute next to `w1_slave`, preferred when present.
+    temperature_file: PathBuf,
+}
+
+impl TemperatureSensor {
+    fn new(id: &str, device_file: String, config: Option<&SensorConfig>) -> Self {
+        let sensor = TemperatureSensor {
+            name: config.map_or_else(|| id.to_string(), |c| c.name.clone()),
+            location: config.and_then(|c| c.location.clone()),
+            temperature_file: Path::new(&device_file).with_file_name("temperature"),
+            device_file,
+        };
+
+        match &sensor.location {
+            Some(location) => info!("Sensor '{}' ({}) at: {}",
+                                    sensor.name, location, sensor.device_file),
+            None => info!("Sensor '{}' at: {}", sensor.name, sensor.device_file),
+        }
+        sensor
+    }
+
+    /// Millidegrees from the `temperature` attribute of newer kernels; `None`
+    /// when the kernel doesn't provide it.
+    fn read_attribute(&self) -> Option<Result<f32>> {
+        let content = match fs::read_to_string(&self.temperature_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return None,
+            Err(e) => {
+                return Some(Err(e).with_context(|| {
+                    format!("Failed to read sensor: {}", self.temperature_file.display())
+                }));
+            }
+        };
+        Some(content.trim().parse::<f32>()
+            .map(|millic| millic / 1000.0)
+            .with_context(|| format!("Failed to parse temperature '{}'", content.trim())))
+    }
+
+    fn read_w1_slave(&self) -> Result<f32> {
+        let mut file = fs::File::open(&self.device_file)
+            .with_context(|| format!("Failed to open sensor: {}", self.device_file))?;
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .context("Failed to read sensor data")?;
+
+        let lines: Vec<&str> = content.lines().collect();
+
+        if lines.len() < 2 {
+            error!("Invalid sensor data format");
+            return Err(anyhow::anyhow!("Invalid sensor data format"));
+        }
+
+        if !lines[0].trim().ends_with("YES") {
+            error!("Sensor CRC check failed");
+            return Err(anyhow::anyhow!("Sensor CRC check failed"));
+        }
+
+        if let Some(temp_line) = lines.get(1)
+            && let Some(pos) = temp_line.find("t=")
+        {
+            let temp_str = &temp_line[pos + 2..];
+            let temp_millic = temp_str.parse::<f32>()
+                .context("Failed to parse temperature")?;
+
+            let temp_c = temp_millic / 1000.0;
+            debug!("Raw temp: {} -> {}°C", temp_millic, temp_c);
+            return Ok(temp_c);
+        }
+
+        error!("Temperature data not found");
+        Err(anyhow::anyhow!("Temperature data not found"))
+    }
+}
+
+impl Sensor for TemperatureSensor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_temperature(&mut self) -> Result<f32> {
+        match self.read_attribute() {
+            Some(temp) => temp,
+            None => self.read_w1_slave(),
+        }
+    }
+
+    fn range(&self) -> Option<(f32, f32)> {
+        Some(W1_RANGE)
+    }
+
+    fn has_error_codes(&self) -> bool {
+        true
+    }
+}
+
+fn rom_id(device_file: &Path) -> Option<String> {
+    device_file.parent()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// The probes under a w1 devices directory. The kernel's bus master adds and
+/// removes device directories as probes come and go; rescans pick those up.
+struct OneWireBus {
+    root: PathBuf,
+    configs: Vec<SensorConfig>,
+    config: OneWireConfig,
+    /// Tracked ROM ids and whether they were on the bus at the last scan.
+    present: BTreeMap<String, bool>,
+    last_scan: Instant,
+}
+
+impl OneWireBus {
+    fn new(root: impl Into<PathBuf>, configs: &[SensorConfig], config: &OneWireConfig) -> Self {
+        OneWireBus {
+            root: root.into(),
+            configs: configs.to_vec(),
+            config: config.clone(),
+            present: BTreeMap::new(),
+            last_scan: Instant::now(),
+        }
+    }
+
+    /// ROM ids of all temperature probes currently on the bus.
+    fn discover(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for (family, chip) in W1_FAMILIES {
+            let pattern = format!("{}/{}-*/w1_slave", self.root.display(), family);
+            for entry in glob(&pattern)? {
+                match entry {
+                    Ok(path) => {
+                        if let Some(id) = rom_id(&path) {
+                            debug!("Found {} at: {}", chip, path.display());
+                            ids.push(id);
+                        }
+                    }
+                    Err(e) => warn!("Error reading sensor path: {}", e),
+                }
+            }
+        }
+
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Sensors for every probe on the bus plus every configured one that is
+    /// missing right now.
+    fn open(&mut self) -> Result<Vec<TemperatureSensor>> {
+        let found = self.discover()?;
+        let mut ids = found.clone();
+        for config in &self.configs {
+            if !ids.contains(&config.id) {
+                warn!("Configured sensor '{}' ({}) not found on the bus",
+                      config.name, config.id);
+                ids.push(config.id.clone());
+            }
+        }
+
+        let sensors = ids.iter().map(|id| self.track(id, found.contains(id))).collect();
+        self.last_scan = Instant::now();
+        Ok(sensors)
+    }
+
+    fn track(&mut self, id: &str, present: bool) -> TemperatureSensor {
+        if present {
+            info!("Found {} {}", family(id).unwrap_or("1-Wire probe"), id);
+            self.set_resolution(id);
+        }
+        self.present.insert(id.to_string(), present);
+        let device_file = format!("{}/{}/w1_slave", self.root.display(), id);
+        TemperatureSensor::new(id, device_file, self.configs.iter().find(|c| c.id == id))
+    }
+
+    /// Sensors for probes hot-plugged since the last scan, if a rescan is due.
+    fn rescan(&mut self) -> Vec<TemperatureSensor> {
+        let interval = self.config.rescan();
+        if interval.is_zero() || self.last_scan.elapsed() < interval {
+            return Vec::new();
+        }
+        self.last_scan = Instant::now();
+        self.scan()
+    }
+
+    fn scan(&mut self) -> Vec<TemperatureSensor> {
+        let found = match self.discover() {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("1-Wire rescan failed: {:#}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut added = Vec::new();
+        for id in &found {
+            match self.present.get(id) {
+                None => {
+                    info!("Sensor {} hot-plugged", id);
+                    added.push(self.track(id, true));
+                }
+                Some(false) => {
+                    info!("Sensor {} is back on the bus", id);
+                    // A probe that lost power is back at its default resolution.
+                    self.set_resolution(id);
+                    self.present.insert(id.clone(), true);
+                }
+                Some(true) => {}
+            }
+        }
+        for (id, present) in self.present.iter_mut() {
+            if *present && !found.contains(id) {
+                warn!("Sensor {} removed from the bus", id);
+                *present = false;
+            }
+        }
+        added
+    }
+
+    /// Writes the configured resolution to the probe's `resolution` attribute.
+    fn set_resolution(&self, id: &str) {
+        let bits = self.configs.iter()
+            .find(|c| c.id == id)
+            .and_then(|c| c.resolution)
+            .or(self.config.resolution);
+        let Some(bits) = bits else {
+            return;
+        };
+        if id.starts_with("10-") {
+            warn!("Sensor {}: the DS18S20 has a fixed resolution, {} bits not set", id, bits);
+            return;
+        }
+
+        let path = self.root.join(id).join("resolution");
+        match fs::write(&path, format!("{}\n", bits)) {
+            Ok(()) => info!("Sensor {}: resolution set to {} bits", id, bits),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!("Sensor {}: kernel has no resolution attribute, {} bits not set", id, bits);
+            }
+            Err(e) => warn!("Sensor {}: failed to set resolution to {} bits: {}", id, bits, e),
+        }
+    }
+}
+
+/// All probes the daemon tracks.
+pub struct SensorSet {
+    sensors: Vec<Box<dyn Sensor>>,
+    /// Bus rescanned for hot-plugged probes; `None` for a fixed set.
+    bus: Option<OneWireBus>,
+    filter: Option<FilterConfig>,
+}
+
+impl SensorSet {
+    /// With `sensor_path` set only that device is used; otherwise every probe
+    /// on the bus is tracked, plus any configured one that is missing right now
+    /// (so its absence shows up as a read error instead of being ignored).
+    /// I2C and hwmon/thermal zone sensors are added to either.
+    pub fn new(sensor_path: Option<&str>, configs: &[SensorConfig], onewire: &OneWireConfig,
+               i2c: &[I2cSensorConfig], sysfs: &[SysfsSensorConfig]) -> Result<Self> {
+        let mut set = match sensor_path {
+            Some(path) => {
+                let id = rom_id(Path::new(path)).unwrap_or_else(|| path.to_string());
+                if let Some(root) = Path::new(path).parent().and_then(Path::parent) {
+                    OneWireBus::new(root, configs, onewire).set_resolution(&id);
+                }
+                let config = configs.iter().find(|c| c.id == id);
+                let sensor = TemperatureSensor::new(&id, path.to_string(), config);
+                Self::from_sensors(vec![Box::new(sensor)])
+            }
+            None => Self::on_bus(W1_DEVICES, configs, onewire)?,
+        };
+        for config in i2c {
+            set.sensors.push(Box::new(I2cSensor::new(config.clone())));
+        }
+        for config in sysfs {
+            set.sensors.push(Box::new(SysfsSensor::new(config.clone())));
+        }
+
+        if set.sensors.is_empty() {
+            return Err(anyhow::anyhow!("No temperature sensor found"));
+        }
+        info!("Tracking {} sensor(s)", set.sensors.len());
+        Ok(set)
+    }
+
+    /// Every probe under a w1 devices directory.
+    fn on_bus(root: impl Into<PathBuf>, configs: &[SensorConfig], onewire: &OneWireConfig)
+              -> Result<Self> {
+        let mut bus = OneWireBus::new(root, configs, onewire);
+        let sensors = bus.open()?;
+        let mut set = Self::from_sensors(sensors.into_iter()
+            .map(|s| Box::new(s) as Box<dyn Sensor>)
+            .collect());
+        set.bus = Some(bus);
+        Ok(set)
+    }
+
+    pub fn from_sensors(sensors: Vec<Box<dyn Sensor>>) -> Self {
+        SensorSet { sensors, bus: None, filter: None }
+    }
+
+    /// Starts tracking probes hot-plugged since the last rescan, once
+    /// `[onewire] rescan_secs` have passed.
+    pub fn rescan(&mut self) {
+        let Some(bus) = self.bus.as_mut() else {
+            return;
+        };
+        for sensor in bus.rescan() {
+            self.add(Box::new(sensor));
+        }
+    }
+
+    fn add(&mut self, sensor: Box<dyn Sensor>) {
+        match &self.filter {
+            Some(filter) => self.sensors.push(Box::new(FilteredSensor::new(sensor, filter.clone()))),
+            None => self.sensors.push(sensor),
+        }
+    }
+
+    /// Puts every sensor behind the `[filter]` sanity checks.
+    pub fn with_filter(self, config: &FilterConfig) -> Self {
+        SensorSet {
+            sensors: self.sensors.into_iter()
+                .map(|s| Box::new(FilteredSensor::new(s, config.clone())) as Box<dyn Sensor>)
+                .collect(),
+            bus: self.bus,
+            filter: Some(config.clone()),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sensors.iter().map(|s| s.name().to_string()).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sensors.iter().any(|s| s.name() == name)
+    }
+
+    pub fn read_all(&mut self) -> Vec<SensorReading> {
+        self.sensors.iter_mut()
+            .map(|sensor| {
+                let temp = sensor.read_temperature();
+                SensorReading {
+                    name: sensor.name().to_string(),
+                    environment: if temp.is_ok() { sensor.environment() } else { Environment::default() },
+                    temp,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn w1_slave(millic: i32) -> String {
+        format!("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t={}\n", millic)
+    }
+
+    fn probe(root: &Path, id: &str, millic: i32) -> PathBuf {
+        let dir = root.join(id);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("w1_slave"), w1_slave(millic)).unwrap();
+        dir
+    }
+
+    fn temperatures(set: &mut SensorSet) -> Vec<(String, f32)> {
+        set.read_all().into_iter()
+            .map(|r| (r.name, r.temp.unwrap_or(f32::NAN)))
+            .collect()
+    }
+
+    #[test]
+    fn reads_all_therm_families_and_sets_resolution() {
+        let root = std::env::temp_dir().join(format!("rackfan-w1-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        probe(&root, "10-000802b4b1c2", 21500);
+        let ds1822 = probe(&root, "22-000003c5a6f1", 99999);
+        fs::write(ds1822.join("temperature"), "22750\n").unwrap();
+        let ds18b20 = probe(&root, "28-0316a2794fff", 23125);
+        probe(&root, "3b-0000001a2b3c", 30000);
+        fs::create_dir_all(root.join("w1_bus_master1")).unwrap();
+
+        let configs = [SensorConfig {
+            id: "28-0316a2794fff".to_string(),
+            name: "inlet".to_string(),
+            location: None,
+            resolution: Some(12),
+        }];
+        let onewire = OneWireConfig { resolution: Some(10), rescan_secs: None };
+        let mut set = SensorSet::on_bus(&root, &configs, &onewire).unwrap();
+
+        // The thermocouple (3b) is not a w1_therm probe; the DS1822 is read
+        // through its temperature attribute.
+        assert_eq!(temperatures(&mut set), vec![
+            ("10-000802b4b1c2".to_string(), 21.5),
+            ("22-000003c5a6f1".to_string(), 22.75),
+            ("inlet".to_string(), 23.125),
+        ]);
+        assert_eq!(fs::read_to_string(ds18b20.join("resolution")).unwrap(), "12\n");
+        assert_eq!(fs::read_to_string(ds1822.join("resolution")).unwrap(), "10\n");
+        assert!(!root.join("10-000802b4b1c2/resolution").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn rescans_pick_up_hot_plugged_probes() {
+        let root = std::env::temp_dir().join(format!("rackfan-w1-hotplug-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        probe(&root, "28-0316a2794fff", 23125);
+
+        let onewire = OneWireConfig { resolution: Some(11), rescan_secs: Some(60) };
+        let mut set = SensorSet::on_bus(&root, &[], &onewire)
+            .unwrap()
+            .with_filter(&FilterConfig::default());
+        let rescan_due = |set: &mut SensorSet| {
+            set.bus.as_mut().unwrap().last_scan -= Duration::from_secs(61);
+            set.rescan();
+        };
+
+        let ds28ea00 = probe(&root, "42-00000015c8a3", 26000);
+        set.rescan();
+        assert_eq!(set.names(), vec!["28-0316a2794fff"]);
+        rescan_due(&mut set);
+        assert_eq!(temperatures(&mut set), vec![
+            ("28-0316a2794fff".to_string(), 23.125),
+            ("42-00000015c8a3".to_string(), 26.0),
+        ]);
+        assert_eq!(fs::read_to_string(ds28ea00.join("resolution")).unwrap(), "11\n");
+
+        // Unplugged and back: tracked throughout, resolution written again.
+        fs::remove_dir_all(&ds28ea00).unwrap();
+        rescan_due(&mut set);
+        assert!(set.read_all()[1].temp.is_err());
+        probe(&root, "42-00000015c8a3", 26500);
+        rescan_due(&mut set);
+        assert_eq!(set.names().len(), 2);
+        assert_eq!(fs::read_to_string(ds28ea00.join("resolution")).unwrap(), "11\n");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
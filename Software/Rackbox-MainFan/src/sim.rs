@@ -0,0 +1,118 @@
+//! In-memory sensor and fan backends, so the control logic can run without a
+//! Raspberry Pi: scripted temperature sequences in, recorded duty changes out.
+
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::fan::FanDriver;
+use crate::sensor::{Sensor, W1_RANGE};
+
+/// Readings a [`ScriptedSensor`] will return, in order. Shared so the script
+/// can be extended while the sensor is owned by a `SensorSet`.
+#[derive(Clone, Default)]
+pub struct SensorScript(Arc<Mutex<VecDeque<Result<f32, String>>>>);
+
+impl SensorScript {
+    pub fn push(&self, temp: f32) {
+        self.push_reading(Ok(temp));
+    }
+
+    pub fn push_error(&self, message: &str) {
+        self.push_reading(Err(message.to_string()));
+    }
+
+    fn push_reading(&self, reading: Result<f32, String>) {
+        if let Ok(mut queue) = self.0.lock() {
+            queue.push_back(reading);
+        }
+    }
+}
+
+/// Sensor returning the next scripted reading each cycle. Once the script is
+/// exhausted the last reading repeats. Stands in for a 1-Wire probe, error
+/// codes included.
+pub struct ScriptedSensor {
+    name: String,
+    script: SensorScript,
+    last: Option<Result<f32, String>>,
+}
+
+impl ScriptedSensor {
+    pub fn new(name: &str) -> (Self, SensorScript) {
+        let script = SensorScript::default();
+        let sensor = ScriptedSensor {
+            name: name.to_string(),
+            script: script.clone(),
+            last: None,
+        };
+        (sensor, script)
+    }
+}
+
+impl Sensor for ScriptedSensor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_temperature(&mut self) -> Result<f32> {
+        if let Some(next) = self.script.0.lock().ok().and_then(|mut q| q.pop_front()) {
+            self.last = Some(next);
+        }
+        match &self.last {
+            Some(Ok(temp)) => Ok(*temp),
+            Some(Err(message)) => Err(anyhow::anyhow!("{}", message)),
+            None => Err(anyhow::anyhow!("No scripted reading for sensor '{}'", self.name)),
+        }
+    }
+
+    fn range(&self) -> Option<(f32, f32)> {
+        Some(W1_RANGE)
+    }
+
+    fn has_error_codes(&self) -> bool {
+        true
+    }
+}
+
+/// Duty values actually applied to a [`RecordingFan`], in order.
+#[derive(Clone, Default)]
+pub struct FanLog(Arc<Mutex<Vec<f32>>>);
+
+#[cfg(test)]
+impl FanLog {
+    pub fn transitions(&self) -> Vec<f32> {
+        self.0.lock().map(|log| log.clone()).unwrap_or_default()
+    }
+
+    pub fn last(&self) -> Option<f32> {
+        self.transitions().last().copied()
+    }
+}
+
+/// Fan output that records every change instead of touching a pin.
+pub struct RecordingFan {
+    proportional: bool,
+    log: FanLog,
+}
+
+impl RecordingFan {
+    /// `proportional` selects PWM-like behaviour; otherwise on/off like a relay.
+    pub fn new(proportional: bool) -> (Self, FanLog) {
+        let log = FanLog::default();
+        (RecordingFan { proportional, log: log.clone() }, log)
+    }
+}
+
+impl FanDriver for RecordingFan {
+    fn proportional(&self) -> bool {
+        self.proportional
+    }
+
+    fn apply(&mut self, duty: f32) -> Result<()> {
+        if let Ok(mut log) = self.log.0.lock() {
+            log.push(duty);
+        }
+        Ok(())
+    }
+}
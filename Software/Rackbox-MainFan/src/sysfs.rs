@@ -0,0 +1,170 @@
+//! `[[sysfs_sensor]]`: temperatures the kernel already exposes under
+//! /sys/class (hwmon chips and thermal zones), found through rackbox_sysfs.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use rackbox_sysfs::{Source, SYS_CLASS, find, inputs};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Problems;
+use crate::sensor::Sensor;
+
+/// `[[sysfs_sensor]]` entry in config.toml.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SysfsSensorConfig {
+    pub name: String,
+    /// Input selected by name, as listed by `rackfan_daemon sensors`:
+    /// `hwmon:<chip>[/<label>]` or `thermal:<type>`.
+    pub source: Source,
+    pub location: Option<String>,
+}
+
+impl SysfsSensorConfig {
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.name.trim().is_empty() {
+            problems.push(format_args!("{}.name", path), "must not be empty");
+        }
+    }
+}
+
+/// Reads a millidegree temperature file.
+fn read_millidegrees(path: &Path) -> Result<f32> {
+    rackbox_sysfs::read_millidegrees(path)
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// hwmon or thermal zone temperature. The input is looked up by name on the
+/// first read and again after an error, since hwmon numbering may change
+/// between boots and a chip's driver may load late. Drivers report failures
+/// as read errors, so `[filter]` has no error codes or range to check.
+pub struct SysfsSensor {
+    config: SysfsSensorConfig,
+    root: PathBuf,
+    path: Option<PathBuf>,
+}
+
+impl SysfsSensor {
+    pub fn new(config: SysfsSensorConfig) -> Self {
+        Self::with_root(config, SYS_CLASS)
+    }
+
+    fn with_root(config: SysfsSensorConfig, root: impl Into<PathBuf>) -> Self {
+        match &config.location {
+            Some(location) => info!("Sensor '{}' ({}) reads {}", config.name, location, config.source),
+            None => info!("Sensor '{}' reads {}", config.name, config.source),
+        }
+        SysfsSensor { config, root: root.into(), path: None }
+    }
+}
+
+impl Sensor for SysfsSensor {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn read_temperature(&mut self) -> Result<f32> {
+        let path = match self.path.take() {
+            Some(path) => path,
+            None => {
+                let path = find(&self.root, &self.config.source).with_context(|| {
+                    format!("{} not found under {}", self.config.source, self.root.display())
+                })?;
+                info!("Sensor '{}': {} is {}", self.config.name, self.config.source, path.display());
+                path
+            }
+        };
+        match read_millidegrees(&path) {
+            Ok(temp) => {
+                self.path = Some(path);
+                Ok(temp)
+            }
+            Err(e) => {
+                warn!("Sensor '{}': {:#}", self.config.name, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+const USAGE: &str = "usage: rackfan_daemon sensors [--root <dir>]";
+
+/// `sensors [--root <dir>]`: lists the hwmon and thermal zone inputs with
+/// their current temperature, named as `source` in `[[sysfs_sensor]]`.
+pub fn run(args: &[String]) -> Result<()> {
+    let mut root = PathBuf::from(SYS_CLASS);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = PathBuf::from(args.next().context(USAGE)?),
+            other => return Err(anyhow::anyhow!("unexpected argument '{}'\n{}", other, USAGE)),
+        }
+    }
+
+    let inputs = inputs(&root);
+    if inputs.is_empty() {
+        println!("no hwmon or thermal zone temperature under {}", root.display());
+    }
+    for input in inputs {
+        let temp = read_millidegrees(&input.path)
+            .map_or_else(|e| format!("error: {:#}", e.root_cause()), |t| format!("{:>6.1}°C", t));
+        println!("{:<36} {:<14} {}", input.source.to_string(), input.alias, temp);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{FilterConfig, FilteredSensor};
+    use std::fs;
+    use std::process;
+
+    /// An NVMe drive as hwmon2; rackbox_sysfs tests the lookup on a full tree.
+    fn fake_nvme(name: &str, millidegrees: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rackfan-sysfs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("hwmon/hwmon2")).unwrap();
+        fs::write(root.join("hwmon/hwmon2/name"), "nvme\n").unwrap();
+        fs::write(root.join("hwmon/hwmon2/temp1_label"), "Composite\n").unwrap();
+        fs::write(root.join("hwmon/hwmon2/temp1_input"), millidegrees).unwrap();
+        root
+    }
+
+    fn ssd() -> SysfsSensorConfig {
+        SysfsSensorConfig {
+            name: "ssd".into(),
+            source: Source::try_from("hwmon:nvme/Composite".to_string()).unwrap(),
+            location: None,
+        }
+    }
+
+    #[test]
+    fn a_hot_drive_at_85_degrees_passes_the_filter() {
+        let root = fake_nvme("hot", "85000\n");
+        let sensor = SysfsSensor::with_root(ssd(), &root);
+        let mut sensor = FilteredSensor::new(Box::new(sensor), FilterConfig::default());
+        assert_eq!(sensor.read_temperature().unwrap(), 85.0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn sensor_follows_its_chip_when_hwmon_is_renumbered() {
+        let root = fake_nvme("renumber", "38850\n");
+        let mut sensor = SysfsSensor::with_root(ssd(), &root);
+        assert_eq!(sensor.read_temperature().unwrap(), 38.85);
+
+        // The drive comes back as hwmon3 (e.g. after a controller reset).
+        fs::rename(root.join("hwmon/hwmon2"), root.join("hwmon/hwmon3")).unwrap();
+        fs::write(root.join("hwmon/hwmon3/temp1_input"), "-1500\n").unwrap();
+        assert!(sensor.read_temperature().is_err());
+        assert_eq!(sensor.read_temperature().unwrap(), -1.5);
+
+        fs::remove_dir_all(root.join("hwmon/hwmon3")).unwrap();
+        assert!(sensor.read_temperature().is_err());
+        let error = sensor.read_temperature().unwrap_err();
+        assert!(format!("{:#}", error).contains("hwmon:nvme/Composite not found"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::Result;
+use log::{error, info};
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::{check_gpio, Problems};
+use crate::hook;
+
+/// `[tach]` table: optional tachometer input for a fan.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TachConfig {
+    pub gpio: u8,
+    /// Tach pulses per revolution; 2 for most PC fans.
+    pub pulses_per_rev: Option<u32>,
+    /// Below this RPM a fan commanded on is considered stalled.
+    pub min_rpm: Option<f32>,
+    /// Time a fan gets to spin up after being switched on before stall checks.
+    pub spinup_secs: Option<u64>,
+    /// Shell command run once when a stall is detected.
+    pub alarm_command: Option<String>,
+}
+
+impl TachConfig {
+    pub fn pulses_per_rev(&self) -> u32 {
+        self.pulses_per_rev.unwrap_or(2)
+    }
+
+    pub fn min_rpm(&self) -> f32 {
+        self.min_rpm.unwrap_or(300.0)
+    }
+
+    pub fn spinup(&self) -> Duration {
+        Duration::from_secs(self.spinup_secs.unwrap_or(10))
+    }
+
+    pub fn resolved(&self) -> Self {
+        TachConfig {
+            pulses_per_rev: Some(self.pulses_per_rev()),
+            min_rpm: Some(self.min_rpm()),
+            spinup_secs: Some(self.spinup().as_secs()),
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        check_gpio(problems, format_args!("{}.gpio", path), self.gpio);
+        if self.pulses_per_rev == Some(0) {
+            problems.push(format_args!("{}.pulses_per_rev", path), "must be at least 1");
+        }
+        if let Some(min_rpm) = self.min_rpm
+            && !(min_rpm.is_finite() && min_rpm >= 0.0)
+        {
+            problems.push(format_args!("{}.min_rpm", path), "must be 0 or more");
+        }
+    }
+}
+
+/// Counts tach pulses on a GPIO with an edge interrupt and turns them into RPM.
+pub struct Tachometer {
+    // Kept alive so the interrupt thread keeps running.
+    _pin: InputPin,
+    pulses: Arc<AtomicU64>,
+    last_count: u64,
+    last_sample: Instant,
+    pulses_per_rev: u32,
+}
+
+impl Tachometer {
+    pub fn new(config: &TachConfig) -> Result<Self> {
+        let gpio = Gpio::new()?;
+        // Fan tach outputs are open collector.
+        let mut pin = gpio.get(config.gpio)?.into_input_pullup();
+
+        let pulses = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&pulses);
+        pin.set_async_interrupt(Trigger::FallingEdge, None, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })?;
+
+        info!("Tachometer on GPIO {}", config.gpio);
+        Ok(Tachometer {
+            _pin: pin,
+            pulses,
+            last_count: 0,
+            last_sample: Instant::now(),
+            pulses_per_rev: config.pulses_per_rev().max(1),
+        })
+    }
+
+    /// Average RPM since the previous call.
+    pub fn sample_rpm(&mut self) -> f32 {
+        let count = self.pulses.load(Ordering::Relaxed);
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sample).as_secs_f32();
+
+        let delta = count - self.last_count;
+        self.last_count = count;
+        self.last_sample = now;
+
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        delta as f32 / self.pulses_per_rev as f32 * 60.0 / elapsed
+    }
+}
+
+/// Tachometer plus stall bookkeeping for one fan.
+pub struct FanMonitor {
+    config: TachConfig,
+    tach: Tachometer,
+    on_since: Option<Instant>,
+    stalled: bool,
+    rpm: f32,
+}
+
+impl FanMonitor {
+    pub fn new(config: TachConfig) -> Result<Self> {
+        let tach = Tachometer::new(&config)?;
+        info!("Stall detection: min {} RPM after {}s spin-up",
+              config.min_rpm(), config.spinup().as_secs());
+        Ok(FanMonitor {
+            config,
+            tach,
+            on_since: None,
+            stalled: false,
+            rpm: 0.0,
+        })
+    }
+
+    /// Applies new thresholds and alarm command; the tach GPIO stays as is.
+    pub fn set_config(&mut self, config: TachConfig) {
+        self.tach.pulses_per_rev = config.pulses_per_rev().max(1);
+        self.config = config;
+    }
+
+    pub fn rpm(&self) -> f32 {
+        self.rpm
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    /// Samples the RPM and checks it against the commanded fan state.
+    pub fn update(&mut self, zone: &str, fan_on: bool) {
+        self.rpm = self.tach.sample_rpm();
+
+        if !fan_on {
+            self.on_since = None;
+            if self.stalled {
+                info!("Zone '{}': fan switched off, clearing stall alarm", zone);
+                self.stalled = false;
+            }
+            return;
+        }
+
+        let on_since = *self.on_since.get_or_insert_with(Instant::now);
+        if on_since.elapsed() < self.config.spinup() {
+            return;
+        }
+
+        let stalled = self.rpm < self.config.min_rpm();
+        if stalled && !self.stalled {
+            if self.rpm == 0.0 {
+                error!("Zone '{}': no tach pulses while fan is on - fan stalled or missing",
+                       zone);
+            } else {
+                error!("Zone '{}': fan stalled at {:.0} RPM (< {:.0} RPM)",
+                       zone, self.rpm, self.config.min_rpm());
+            }
+            if let Some(command) = &self.config.alarm_command {
+                hook::run_command(command, &[
+                    ("RACKFAN_ZONE", zone.to_string()),
+                    ("RACKFAN_RPM", format!("{:.0}", self.rpm)),
+                ]);
+            }
+        } else if !stalled && self.stalled {
+            info!("Zone '{}': fan recovered at {:.0} RPM", zone, self.rpm);
+        }
+        self.stalled = stalled;
+    }
+}
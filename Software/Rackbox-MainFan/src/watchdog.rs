@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use crate::config::Problems;
+
+const DEFAULT_DEVICE: &str = "/dev/watchdog";
+
+/// `_IOWR('W', 6, int)` from linux/watchdog.h: sets the timeout in seconds
+/// and writes back the value the driver actually uses.
+const WDIOC_SETTIMEOUT: libc::c_ulong = 0xC004_5706;
+
+/// `[watchdog]` table: hardware watchdog that reboots the Pi if the control
+/// loop stops completing cycles.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    pub device: Option<String>,
+    /// Seconds without a successful cycle before the board resets. The
+    /// Raspberry Pi watchdog supports at most 15.
+    pub timeout_secs: Option<u32>,
+}
+
+impl WatchdogConfig {
+    pub fn device(&self) -> &str {
+        self.device.as_deref().unwrap_or(DEFAULT_DEVICE)
+    }
+
+    pub fn timeout_secs(&self) -> u32 {
+        self.timeout_secs.unwrap_or(15)
+    }
+
+    pub fn resolved(&self) -> Self {
+        WatchdogConfig {
+            device: Some(self.device().to_string()),
+            timeout_secs: Some(self.timeout_secs()),
+        }
+    }
+
+    /// The timeout must cover a whole cycle: the wait between cycles plus
+    /// `read_time`, the longest the sensors may take to read.
+    pub fn validate(&self, path: &str, check_interval_secs: u64, read_time: Duration,
+                    problems: &mut Problems) {
+        let cycle = Duration::from_secs(check_interval_secs) + read_time;
+        if Duration::from_secs(self.timeout_secs().into()) <= cycle {
+            problems.push(format_args!("{}.timeout_secs", path), format_args!(
+                "must be longer than check_interval_secs ({}) plus the worst-case sensor \
+                 read time ({:.2}s)", check_interval_secs, read_time.as_secs_f32()));
+        }
+    }
+}
+
+/// Open watchdog device. Dropping it without [`Watchdog::close`] leaves the
+/// watchdog armed, so a crash still ends in a reset.
+pub struct Watchdog {
+    file: File,
+    device: String,
+}
+
+impl Watchdog {
+    /// Opens and arms the watchdog with the configured timeout.
+    pub fn open(config: &WatchdogConfig) -> Result<Self> {
+        let device = config.device();
+        let file = OpenOptions::new()
+            .write(true)
+            .open(device)
+            .with_context(|| format!("Failed to open watchdog {}", device))?;
+
+        let mut timeout = config.timeout_secs() as libc::c_int;
+        // SAFETY: WDIOC_SETTIMEOUT reads and writes one int through the pointer.
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), WDIOC_SETTIMEOUT, &mut timeout) };
+        if ret < 0 {
+            warn!("Watchdog {}: could not set timeout to {}s: {}",
+                  device, config.timeout_secs(), std::io::Error::last_os_error());
+        } else if timeout as u32 != config.timeout_secs() {
+            warn!("Watchdog {}: driver uses a {}s timeout instead of {}s",
+                  device, timeout, config.timeout_secs());
+        }
+
+        info!("Watchdog {} armed ({}s timeout)", device, timeout);
+        Ok(Self::from_file(file, device.to_string()))
+    }
+
+    pub fn from_file(file: File, device: String) -> Self {
+        Watchdog { file, device }
+    }
+
+    pub fn pet(&mut self) {
+        if let Err(e) = self.file.write_all(b"\0") {
+            warn!("Watchdog {}: keepalive failed: {}", self.device, e);
+        }
+    }
+
+    /// Magic close: disarms the watchdog on a clean shutdown.
+    pub fn close(mut self) {
+        match self.file.write_all(b"V") {
+            Ok(()) => info!("Watchdog {} disarmed", self.device),
+            Err(e) => warn!("Watchdog {}: magic close failed: {}", self.device, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn pets_with_writes_and_disarms_with_magic_close() {
+        let path = std::env::temp_dir()
+            .join(format!("rackfan-watchdog-{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+
+        let mut watchdog = Watchdog::from_file(file, path.display().to_string());
+        watchdog.pet();
+        watchdog.pet();
+        watchdog.close();
+
+        assert_eq!(fs::read(&path).unwrap(), b"\0\0V");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1200 @@
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::alarm::{Alarm, AlarmConfig, AlarmLevel};
+use crate::config::{check_gpio, Problems};
+use crate::cycling::{CycleGuard, CyclingConfig};
+use crate::failsafe::{FailsafeConfig, Health};
+use crate::fan::{FanController, FanMode, PwmConfig};
+use crate::hook::{self, HooksConfig};
+use crate::humidity::HumidityConfig;
+use crate::manual::ManualConfig;
+use crate::pid::{PidConfig, PidController};
+use crate::profile::{Limits, ProfileConfig};
+use crate::sensor::{SensorPolicy, SensorReading};
+use crate::tach::{FanMonitor, TachConfig};
+
+/// `[[zone]]` table in config.toml. Unset fields fall back to the top-level
+/// values of the same name.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    pub name: String,
+    /// Sensor names feeding this zone; all sensors when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensors: Vec<String>,
+    pub fan_gpio: u8,
+    pub temp_minima: Option<f32>,
+    pub temp_maxima: Option<f32>,
+    pub fan_mode: Option<FanMode>,
+    pub pwm: Option<PwmConfig>,
+    pub pid: Option<PidConfig>,
+    pub sensor_policy: Option<SensorPolicy>,
+    pub failsafe: Option<FailsafeConfig>,
+    pub cycling: Option<CyclingConfig>,
+    pub alarm: Option<AlarmConfig>,
+    pub humidity: Option<HumidityConfig>,
+    pub tach: Option<TachConfig>,
+}
+
+/// Effective settings of one zone after defaults have been applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneSettings {
+    pub name: String,
+    pub sensors: Vec<String>,
+    pub fan_gpio: u8,
+    pub temp_minima: f32,
+    pub temp_maxima: f32,
+    pub fan_mode: FanMode,
+    pub pwm: Option<PwmConfig>,
+    pub pid: Option<PidConfig>,
+    pub sensor_policy: SensorPolicy,
+    pub failsafe: FailsafeConfig,
+    pub cycling: CyclingConfig,
+    pub alarm: AlarmConfig,
+    pub humidity: HumidityConfig,
+    pub manual: ManualConfig,
+    pub hooks: HooksConfig,
+    pub tach: Option<TachConfig>,
+}
+
+impl ZoneConfig {
+    /// Checks the fields set in this table; rules involving inherited
+    /// top-level values are checked on the effective settings.
+    pub fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.name.trim().is_empty() {
+            problems.push(format_args!("{}.name", path), "must not be empty");
+        }
+        check_gpio(problems, format_args!("{}.fan_gpio", path), self.fan_gpio);
+        if let Some(pwm) = &self.pwm {
+            pwm.validate(&format!("{}.pwm", path), problems);
+        }
+        if let Some(pid) = &self.pid {
+            pid.validate(&format!("{}.pid", path), problems);
+        }
+        if let Some(failsafe) = &self.failsafe {
+            failsafe.validate(&format!("{}.failsafe", path), problems);
+        }
+        if let Some(cycling) = &self.cycling {
+            cycling.validate(&format!("{}.cycling", path), problems);
+        }
+        if let Some(alarm) = &self.alarm {
+            alarm.validate(&format!("{}.alarm", path), problems);
+        }
+        if let Some(humidity) = &self.humidity {
+            humidity.validate(&format!("{}.humidity", path), problems);
+        }
+        if let Some(tach) = &self.tach {
+            tach.validate(&format!("{}.tach", path), problems);
+        }
+    }
+}
+
+impl ZoneSettings {
+    /// Hardware this zone claims. Changing it needs a restart, everything
+    /// else can be reloaded live.
+    pub fn output_key(&self) -> OutputKey {
+        let pwm = match self.fan_mode {
+            FanMode::OnOff => None,
+            FanMode::Pwm | FanMode::Pid => {
+                let pwm = self.pwm.clone().unwrap_or_else(PwmConfig::software);
+                Some((pwm.channel, pwm.frequency()))
+            }
+        };
+        OutputKey {
+            fan_gpio: self.fan_gpio,
+            pwm,
+            tach_gpio: self.tach.as_ref().map(|t| t.gpio),
+        }
+    }
+
+    /// Every default filled in, including the software PWM output of a pwm or
+    /// pid zone without a `[pwm]` table.
+    pub fn resolved(&self) -> ZoneSettings {
+        let pwm = match self.fan_mode {
+            FanMode::OnOff => self.pwm.clone(),
+            FanMode::Pwm | FanMode::Pid => {
+                Some(self.pwm.clone().unwrap_or_else(PwmConfig::software))
+            }
+        };
+        ZoneSettings {
+            pwm: pwm.as_ref().map(PwmConfig::resolved),
+            pid: self.pid.as_ref().map(PidConfig::resolved),
+            failsafe: self.failsafe.resolved(),
+            cycling: self.cycling.resolved(),
+            alarm: self.alarm.resolved(),
+            humidity: self.humidity.resolved(),
+            manual: self.manual.resolved(),
+            hooks: self.hooks.resolved(),
+            tach: self.tach.as_ref().map(TachConfig::resolved),
+            ..self.clone()
+        }
+    }
+
+    fn selects(&self, sensor: &str) -> bool {
+        self.sensors.is_empty() || self.sensors.iter().any(|s| s == sensor)
+    }
+}
+
+/// A `[[zone]]` table with every setting written out; `[manual]` and
+/// `[hooks]` are global and not part of it.
+impl From<ZoneSettings> for ZoneConfig {
+    fn from(zone: ZoneSettings) -> Self {
+        ZoneConfig {
+            name: zone.name,
+            sensors: zone.sensors,
+            fan_gpio: zone.fan_gpio,
+            temp_minima: Some(zone.temp_minima),
+            temp_maxima: Some(zone.temp_maxima),
+            fan_mode: Some(zone.fan_mode),
+            pwm: zone.pwm,
+            pid: zone.pid,
+            sensor_policy: Some(zone.sensor_policy),
+            failsafe: Some(zone.failsafe),
+            cycling: Some(zone.cycling),
+            alarm: Some(zone.alarm),
+            humidity: Some(zone.humidity),
+            tach: zone.tach,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OutputKey {
+    fan_gpio: u8,
+    /// Hardware channel and frequency, `None` for on/off outputs.
+    pwm: Option<(Option<u8>, f64)>,
+    tach_gpio: Option<u8>,
+}
+
+/// Who decides the fan output of a zone.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Control {
+    /// Temperature control as configured.
+    #[default]
+    Auto,
+    /// Forced fully on through the control socket, for a limited time.
+    On,
+    /// Forced off through the control socket, for a limited time. A sensor
+    /// fail-safe or the `[manual]` off ceiling still turn the fan on.
+    Off,
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Control::Auto => write!(f, "auto"),
+            Control::On => write!(f, "on"),
+            Control::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// Snapshot of a zone for the control socket.
+#[derive(Serialize)]
+pub struct ZoneStatus {
+    pub name: String,
+    pub fan_mode: String,
+    pub control: Control,
+    /// Seconds until a forced state returns to auto.
+    pub control_remaining_secs: Option<u64>,
+    pub temperature: Option<f32>,
+    /// Highest relative humidity of the zone's sensors, and whether it keeps
+    /// the fan running.
+    pub humidity: Option<f32>,
+    pub humid: bool,
+    pub temp_minima: f32,
+    pub temp_maxima: f32,
+    pub fan_on: bool,
+    pub duty: f32,
+    pub switches: u64,
+    pub rpm: Option<f32>,
+    pub health: String,
+    pub failsafe_activations: u64,
+    pub alarm: AlarmLevel,
+    /// Time-based profile in effect, and whether a critical temperature has
+    /// suspended it.
+    pub profile: Option<String>,
+    pub profile_suspended: bool,
+    /// Switches held back by `[cycling]`, per reason.
+    pub suppressed_switches: BTreeMap<&'static str, u64>,
+    pub last_reason: Option<String>,
+}
+
+#[derive(PartialEq)]
+enum FanAction {
+    Off,
+    On,
+    NoChange,
+}
+
+/// One fan output driven by its own sensors, thresholds and control mode.
+pub struct Zone {
+    pub settings: ZoneSettings,
+    fan: Arc<Mutex<FanController>>,
+    last_action: FanAction,
+    pid: Option<PidController>,
+    monitor: Option<FanMonitor>,
+    health: Health,
+    /// Control temperature of the last cycle, `None` if it couldn't be read.
+    temperature: Option<f32>,
+    humidity: Option<f32>,
+    /// Humidity is above `[humidity] max` (with hysteresis): the fan runs.
+    humid: bool,
+    /// Seconds since the first of the current run of failed reads.
+    failing_secs: f32,
+    lost_reported: bool,
+    failsafe_activations: u64,
+    alarm: Alarm,
+    guard: CycleGuard,
+    /// Suppression in effect, logged and counted once per episode.
+    holding: Option<&'static str>,
+    suppressed_switches: BTreeMap<&'static str, u64>,
+    profile: Option<ProfileConfig>,
+    /// The control temperature reached the profile's critical one; the
+    /// zone's own limits apply until it is back under the profile maximum.
+    profile_suspended: bool,
+    control: Control,
+    /// Seconds left of a forced on/off, counted down by `check`.
+    override_left: Option<f32>,
+    /// Why the fan last changed state, for status queries.
+    last_reason: Option<String>,
+}
+
+impl Zone {
+    pub fn new(settings: ZoneSettings) -> Result<Self> {
+        let fan = match (settings.fan_mode, &settings.pwm) {
+            (FanMode::Pwm, Some(pwm)) => {
+                info!("Zone '{}': PWM mode, min duty {}%, curve {:?}",
+                      settings.name, pwm.min_duty.unwrap_or(0.0),
+                      pwm.curve.iter().map(|p| (p.temp, p.duty)).collect::<Vec<_>>());
+                FanController::new_pwm(settings.fan_gpio, pwm)?
+            }
+            (FanMode::Pid, pwm) => {
+                let pwm = pwm.clone().unwrap_or_else(PwmConfig::software);
+                FanController::new_pwm(settings.fan_gpio, &pwm)?
+            }
+            _ => FanController::new(settings.fan_gpio)?,
+        };
+
+        let monitor = settings.tach.clone().map(FanMonitor::new).transpose()?;
+
+        let mut zone = Self::with_fan(settings, fan);
+        zone.monitor = monitor;
+        Ok(zone)
+    }
+
+    /// Zone driving an already constructed fan, without tachometer.
+    pub fn with_fan(settings: ZoneSettings, fan: FanController) -> Self {
+        info!("Zone '{}': Min: {}°C, Max: {}°C, GPIO: {}, sensors: {}",
+              settings.name, settings.temp_minima, settings.temp_maxima,
+              settings.fan_gpio,
+              if settings.sensors.is_empty() { "all".to_string() } else { settings.sensors.join(", ") });
+
+        let pid = match (settings.fan_mode, &settings.pid) {
+            (FanMode::Pid, Some(pid)) => {
+                info!("Zone '{}': PID mode, setpoint {}°C, Kp {}, Ki {}, Kd {}, output {:?}",
+                      settings.name, pid.setpoint, pid.kp, pid.ki, pid.kd, pid.output_limits());
+                Some(PidController::new(pid.clone()))
+            }
+            _ => None,
+        };
+
+        Zone {
+            settings,
+            fan: Arc::new(Mutex::new(fan)),
+            last_action: FanAction::NoChange,
+            pid,
+            monitor: None,
+            health: Health::Ok,
+            temperature: None,
+            humidity: None,
+            humid: false,
+            failing_secs: 0.0,
+            lost_reported: false,
+            failsafe_activations: 0,
+            alarm: Alarm::default(),
+            guard: CycleGuard::default(),
+            holding: None,
+            suppressed_switches: BTreeMap::new(),
+            profile: None,
+            profile_suspended: false,
+            control: Control::Auto,
+            override_left: None,
+            last_reason: None,
+        }
+    }
+
+    /// Applies reloaded settings that use the same hardware, keeping the fan
+    /// state, PID integral and stall bookkeeping.
+    pub fn reconfigure(&mut self, settings: ZoneSettings) {
+        self.pid = match (settings.fan_mode, &settings.pid, self.pid.take()) {
+            (FanMode::Pid, Some(config), Some(mut pid)) => {
+                pid.set_config(config.clone());
+                Some(pid)
+            }
+            (FanMode::Pid, Some(config), None) => Some(PidController::new(config.clone())),
+            _ => None,
+        };
+
+        if let (Some(monitor), Some(tach)) = (self.monitor.as_mut(), &settings.tach) {
+            monitor.set_config(tach.clone());
+        }
+
+        info!("Zone '{}': Min: {}°C, Max: {}°C, mode {:?}, policy {}",
+              settings.name, settings.temp_minima, settings.temp_maxima,
+              settings.fan_mode, settings.sensor_policy);
+        self.settings = settings;
+    }
+
+    /// Duty currently applied to the fan, in percent.
+    pub fn duty(&self) -> f32 {
+        self.fan.lock().map(|fan| fan.duty()).unwrap_or(0.0)
+    }
+
+    /// One-line state for status displays, e.g. `rack 27.4°C fan 60%`.
+    pub fn summary(&self) -> String {
+        let mut temp = self.temperature.map_or("error".to_string(), |t| format!("{:.1}°C", t));
+        if let Some(humidity) = self.humidity {
+            temp.push_str(&format!(" {:.0}%RH", humidity));
+        }
+        let mut summary = format!("{} {} fan {:.0}%", self.settings.name, temp, self.duty());
+        if let Some(profile) = &self.profile
+            && !self.profile_suspended
+        {
+            summary.push_str(&format!(" [{}]", profile.name));
+        }
+        if self.alarm.level() != AlarmLevel::Normal {
+            summary.push_str(&format!(" {}", self.alarm.level().to_string().to_uppercase()));
+        }
+        if self.health != Health::Ok {
+            summary.push_str(&format!(" ({})", self.health));
+        }
+        summary
+    }
+
+    pub fn status(&self) -> ZoneStatus {
+        let (fan_on, duty, switches) = self.fan.lock()
+            .map(|fan| (fan.is_on(), fan.duty(), fan.switches()))
+            .unwrap_or((false, 0.0, 0));
+        ZoneStatus {
+            name: self.settings.name.clone(),
+            fan_mode: self.settings.fan_mode.to_string(),
+            control: self.control,
+            control_remaining_secs: self.override_left.map(|secs| secs.ceil() as u64),
+            temperature: self.temperature,
+            humidity: self.humidity,
+            humid: self.humid,
+            temp_minima: self.limits().temp_minima,
+            temp_maxima: self.limits().temp_maxima,
+            fan_on,
+            duty,
+            switches,
+            rpm: self.monitor.as_ref().map(FanMonitor::rpm),
+            health: self.health.to_string(),
+            failsafe_activations: self.failsafe_activations,
+            alarm: self.alarm.level(),
+            profile: self.profile.as_ref().map(|p| p.name.clone()),
+            profile_suspended: self.profile_suspended,
+            suppressed_switches: self.suppressed_switches.clone(),
+            last_reason: self.last_reason.clone(),
+        }
+    }
+
+    /// Forces the fan on or off for `duration` (the `[manual]` maximum when
+    /// unset), or hands it back to temperature control from the next cycle.
+    pub fn set_control(&mut self, control: Control, duration: Option<Duration>) -> Result<()> {
+        let duty = match control {
+            Control::Auto => {
+                if self.control != Control::Auto {
+                    self.end_override("ended on request");
+                }
+                return Ok(());
+            }
+            Control::On => 100.0,
+            Control::Off => 0.0,
+        };
+
+        let was_on = self.fan_on();
+        let max = self.settings.manual.max_duration();
+        let duration = duration.unwrap_or(max);
+        if duration.is_zero() || duration > max {
+            return Err(anyhow::anyhow!("override duration must be between 1s and {}s",
+                                       max.as_secs()));
+        }
+
+        info!("Zone '{}': override start, fan forced {} for {}s",
+              self.settings.name, control, duration.as_secs());
+        self.control = control;
+        self.override_left = Some(duration.as_secs_f32());
+        if let Ok(mut fan) = self.fan.lock() {
+            fan.set_duty(duty);
+        }
+        self.last_action = if duty > 0.0 { FanAction::On } else { FanAction::Off };
+        self.last_reason = Some(format!("forced {} for {}s through the control socket",
+                                        control, duration.as_secs()));
+        self.run_fan_hook(was_on);
+        Ok(())
+    }
+
+    /// Keeps the fan at the forced output, restoring it when a fail-safe
+    /// turned it on in the meantime.
+    fn hold_override(&mut self, temp: f32) {
+        let duty = if self.control == Control::On { 100.0 } else { 0.0 };
+        let restored = match self.fan.lock() {
+            Ok(mut fan) if fan.duty() != duty => {
+                fan.set_duty(duty);
+                true
+            }
+            _ => false,
+        };
+        if restored {
+            info!("Zone '{}': readings back ({:.1}°C), fan forced {} again",
+                  self.settings.name, temp, self.control);
+            self.last_action = if duty > 0.0 { FanAction::On } else { FanAction::Off };
+            self.last_reason = Some(format!("forced {} again after the fail-safe", self.control));
+        }
+    }
+
+    fn end_override(&mut self, why: &str) {
+        info!("Zone '{}': override end ({}), forced {} -> automatic control",
+              self.settings.name, why, self.control);
+        self.last_reason = Some(format!("override {}, automatic control", why));
+        self.control = Control::Auto;
+        self.override_left = None;
+    }
+
+    /// Replaces the on/off thresholds until the next reload or restart.
+    pub fn set_thresholds(&mut self, temp_minima: f32, temp_maxima: f32) -> Result<()> {
+        if !temp_minima.is_finite() || !temp_maxima.is_finite() || temp_minima >= temp_maxima {
+            return Err(anyhow::anyhow!(
+                "temp_minima must be lower than temp_maxima ({} >= {})", temp_minima, temp_maxima));
+        }
+        info!("Zone '{}': thresholds set to {}°C / {}°C until next reload",
+              self.settings.name, temp_minima, temp_maxima);
+        self.settings.temp_minima = temp_minima;
+        self.settings.temp_maxima = temp_maxima;
+        Ok(())
+    }
+
+    /// Switches to the profile covering the current time, if any.
+    pub fn set_profile(&mut self, profile: Option<&ProfileConfig>) {
+        if self.profile.as_ref() == profile {
+            return;
+        }
+        match (&self.profile, profile) {
+            (_, Some(new)) => {
+                let limits = new.limits(&self.settings);
+                info!("Zone '{}': profile {} active: {}-{}°C, max duty {:.0}%, critical {:.1}°C",
+                      self.settings.name, new, limits.temp_minima, limits.temp_maxima,
+                      limits.max_duty, new.critical_temp(&self.settings));
+            }
+            (Some(old), None) => info!("Zone '{}': profile {} ended, normal limits",
+                                       self.settings.name, old),
+            (None, None) => {}
+        }
+        self.profile = profile.cloned();
+        self.profile_suspended = false;
+    }
+
+    /// Thresholds and duty cap in effect: the active profile's unless a
+    /// critical temperature suspended it.
+    fn limits(&self) -> Limits {
+        match &self.profile {
+            Some(profile) if !self.profile_suspended => profile.limits(&self.settings),
+            _ => Limits {
+                temp_minima: self.settings.temp_minima,
+                temp_maxima: self.settings.temp_maxima,
+                max_duty: 100.0,
+            },
+        }
+    }
+
+    /// Suspends the profile at its critical temperature and resumes it once
+    /// the temperature is back under the profile's maximum.
+    fn check_critical(&mut self, temp: f32) {
+        let Some(profile) = &self.profile else {
+            return;
+        };
+        let critical = profile.critical_temp(&self.settings);
+        if !self.profile_suspended && temp >= critical {
+            warn!("Zone '{}': {:.1}°C reached the critical {:.1}°C, profile {} suspended",
+                  self.settings.name, temp, critical, profile);
+            self.profile_suspended = true;
+        } else if self.profile_suspended && temp < profile.limits(&self.settings).temp_maxima {
+            info!("Zone '{}': {:.1}°C, profile {} resumed", self.settings.name, temp, profile);
+            self.profile_suspended = false;
+        }
+    }
+
+    /// Whether the last update of the fan output went through.
+    pub fn output_ok(&self) -> bool {
+        self.fan.lock().is_ok_and(|fan| !fan.is_faulty())
+    }
+
+    pub fn turn_off(&self) {
+        if let Ok(mut fan) = self.fan.lock() {
+            fan.turn_off();
+        }
+    }
+
+    /// Runs one control cycle; `dt` is the time in seconds since the last one.
+    pub fn check(&mut self, readings: &[SensorReading], dt: f32) {
+        let was_on = self.fan_on();
+        self.guard.tick(dt, was_on);
+        if let Some(left) = self.override_left {
+            if left <= dt {
+                self.end_override("expired");
+            } else {
+                self.override_left = Some(left - dt);
+            }
+        }
+
+        let selected: Vec<&SensorReading> = readings.iter()
+            .filter(|r| self.settings.selects(&r.name))
+            .collect();
+
+        let temp = self.settings.sensor_policy.apply(&selected);
+        self.temperature = temp.as_ref().ok().copied();
+        self.check_humidity(&selected);
+        if let Ok(temp) = temp {
+            self.readings_recovered();
+            self.check_critical(temp);
+        }
+        self.alarm.update(&self.settings.alarm, &self.settings.name, self.temperature, dt);
+        if let Ok(temp) = temp
+            && self.control == Control::Off
+            && temp >= self.settings.manual.off_ceiling(self.settings.temp_maxima)
+        {
+            warn!("Zone '{}': {:.1}°C reached the off-override ceiling of {:.1}°C",
+                  self.settings.name, temp,
+                  self.settings.manual.off_ceiling(self.settings.temp_maxima));
+            self.end_override("cancelled by the safety ceiling");
+        }
+
+        match temp {
+            // Forced through the control socket: readings are tracked but
+            // only a fail-safe may change the fan, until readings recover.
+            Ok(temp) if self.control != Control::Auto => self.hold_override(temp),
+            Ok(temp) if self.settings.fan_mode == FanMode::Pwm => {
+                let duty = self.settings.pwm.as_ref()
+                    .map_or(100.0, |pwm| pwm.duty_for(temp));
+                self.apply_duty(duty, temp);
+            }
+            Ok(temp) if self.settings.fan_mode == FanMode::Pid => {
+                let duty = self.pid_duty(temp, dt);
+                self.apply_duty(duty, temp);
+            }
+            Ok(temp) => {
+                let limits = self.limits();
+                let action = if temp > limits.temp_maxima || self.humid {
+                    FanAction::On
+                } else if temp < limits.temp_minima {
+                    FanAction::Off
+                } else {
+                    FanAction::NoChange
+                };
+
+                self.apply_action(action, temp);
+            }
+            Err(e) => self.readings_failed(e, dt),
+        }
+
+        self.guard.observe(self.fan_on());
+        self.run_fan_hook(was_on);
+        self.check_tach();
+    }
+
+    fn fan_on(&self) -> bool {
+        self.fan.lock().map(|fan| fan.is_on()).unwrap_or(false)
+    }
+
+    /// Updates the zone's humidity from the sensors that report one and
+    /// whether it is high enough to run the fan. Without such a reading the
+    /// previous state is kept.
+    fn check_humidity(&mut self, selected: &[&SensorReading]) {
+        self.humidity = selected.iter()
+            .filter_map(|r| r.environment.humidity)
+            .reduce(f32::max);
+        let Some(humidity) = self.humidity else {
+            return;
+        };
+        let config = &self.settings.humidity;
+        let humid = config.is_humid(humidity, self.humid);
+        if humid != self.humid {
+            let max = config.max.unwrap_or(100.0);
+            if humid {
+                info!("Zone '{}': humidity {:.0}% above {:.0}%, fan runs",
+                      self.settings.name, humidity, max);
+            } else {
+                info!("Zone '{}': humidity {:.0}% back under {:.0}%",
+                      self.settings.name, humidity, max - config.hysteresis());
+            }
+            self.humid = humid;
+        }
+    }
+
+    /// Runs `[hooks]` on_fan_on/on_fan_off if the fan switched since it was
+    /// `was_on`.
+    fn run_fan_hook(&self, was_on: bool) {
+        let on = self.fan_on();
+        if on == was_on {
+            return;
+        }
+        let state = |on| if on { "on" } else { "off" }.to_string();
+        self.settings.hooks.run(if on { "on_fan_on" } else { "on_fan_off" }, &[
+            ("RACKFAN_ZONE", self.settings.name.clone()),
+            ("RACKFAN_TEMP", self.temperature.map_or(String::new(), |t| format!("{:.1}", t))),
+            ("RACKFAN_PREVIOUS_STATE", state(was_on)),
+            ("RACKFAN_STATE", state(on)),
+            ("RACKFAN_DUTY", format!("{:.0}", self.duty())),
+            ("RACKFAN_REASON", self.last_reason.clone().unwrap_or_default()),
+        ]);
+    }
+
+    /// Whether `[cycling]` lets the fan switch now. A temperature at or above
+    /// the critical one always switches it on.
+    fn switch_allowed(&mut self, on: bool, temp: f32) -> bool {
+        let cycling = &self.settings.cycling;
+        let Err(suppression) = self.guard.check(cycling, on) else {
+            return true;
+        };
+        let critical = cycling.critical_temp(self.settings.temp_maxima);
+        if on && temp >= critical {
+            warn!("Zone '{}': {:.1}°C at or above critical {:.1}°C, switching on despite {}",
+                  self.settings.name, temp, critical, suppression);
+            return true;
+        }
+
+        let kind = suppression.kind();
+        if self.holding != Some(kind) {
+            info!("Zone '{}': switch {} suppressed at {:.1}°C ({})",
+                  self.settings.name, if on { "on" } else { "off" }, temp, suppression);
+            *self.suppressed_switches.entry(kind).or_default() += 1;
+            self.holding = Some(kind);
+        }
+        false
+    }
+
+    /// Escalates a failed cycle: degraded (fan held) for the first
+    /// `failsafe.failures - 1` cycles, then fail-safe (fan forced on), and a
+    /// one-off "sensor lost" alert once failures last `lost_after`.
+    fn readings_failed(&mut self, e: anyhow::Error, dt: f32) {
+        let failures = self.health.failures() + 1;
+        if failures > 1 {
+            self.failing_secs += dt;
+        }
+
+        let failsafe = &self.settings.failsafe;
+        if failures >= failsafe.failures() {
+            error!("Zone '{}': temperature read error: {}", self.settings.name, e);
+            if !matches!(self.health, Health::FailSafe { .. }) {
+                error!("Zone '{}': fail-safe after {} failed read(s), fan forced on",
+                       self.settings.name, failures);
+                self.last_reason = Some(format!("fail-safe after {} failed read(s): {}",
+                                                failures, e));
+                self.failsafe_activations += 1;
+                self.settings.hooks.run("on_failsafe", &[
+                    ("RACKFAN_ZONE", self.settings.name.clone()),
+                    ("RACKFAN_FAILURES", failures.to_string()),
+                    ("RACKFAN_ERROR", format!("{:#}", e)),
+                    ("RACKFAN_REASON", self.last_reason.clone().unwrap_or_default()),
+                ]);
+            }
+            if let Ok(mut fan) = self.fan.lock() {
+                fan.turn_on();
+            }
+            self.last_action = FanAction::On;
+            self.health = Health::FailSafe { failures };
+        } else {
+            self.health = Health::Degraded { failures };
+            warn!("Zone '{}': {}, fail-safe at {}, holding fan state: {}",
+                  self.settings.name, self.health, failsafe.failures(), e);
+        }
+
+        let lost_after = failsafe.lost_after().as_secs_f32();
+        if !self.lost_reported && self.failing_secs >= lost_after && lost_after > 0.0 {
+            error!("Zone '{}': sensor lost, no valid reading for {:.0}s",
+                   self.settings.name, self.failing_secs);
+            if let Some(command) = &failsafe.lost_command {
+                hook::run_command(command, &[
+                    ("RACKFAN_ZONE", self.settings.name.clone()),
+                    ("RACKFAN_FAILURES", failures.to_string()),
+                    ("RACKFAN_LOST_SECS", format!("{:.0}", self.failing_secs)),
+                    ("RACKFAN_ERROR", format!("{:#}", e)),
+                ]);
+            }
+            self.lost_reported = true;
+        }
+    }
+
+    fn readings_recovered(&mut self) {
+        if self.health == Health::Ok {
+            return;
+        }
+        info!("Zone '{}': sensor readings recovered after {} failed read(s) ({:.0}s)",
+              self.settings.name, self.health.failures(), self.failing_secs);
+        self.health = Health::Ok;
+        self.failing_secs = 0.0;
+        self.lost_reported = false;
+    }
+
+    fn check_tach(&mut self) {
+        let Some(monitor) = self.monitor.as_mut() else {
+            return;
+        };
+
+        let fan_on = self.fan.lock().map(|fan| fan.is_on()).unwrap_or(false);
+        monitor.update(&self.settings.name, fan_on);
+
+        if monitor.is_stalled() {
+            warn!("Zone '{}': fan {:.0} RPM (stalled)", self.settings.name, monitor.rpm());
+        } else {
+            info!("Zone '{}': fan {:.0} RPM", self.settings.name, monitor.rpm());
+        }
+    }
+
+    fn apply_action(&mut self, action: FanAction, temp: f32) {
+        let limits = self.limits();
+        if action != self.last_action {
+            let switch = match action {
+                FanAction::On => Some(true),
+                FanAction::Off => Some(false),
+                FanAction::NoChange => None,
+            };
+            if let Some(on) = switch
+                && on != self.fan_on()
+                && !self.switch_allowed(on, temp)
+            {
+                return;
+            }
+            self.holding = None;
+
+            match action {
+                FanAction::Off => {
+                    if let Ok(mut fan) = self.fan.lock() {
+                        fan.turn_off();
+                    }
+                    info!("Rackbox fan desligado [{}] (Temp: {:.1}°C < {:.1}°C)",
+                          self.settings.name, temp, limits.temp_minima);
+                    self.last_reason = Some(format!("temp {:.1}°C < {:.1}°C",
+                                                    temp, limits.temp_minima));
+                }
+                FanAction::On => {
+                    if let Ok(mut fan) = self.fan.lock() {
+                        fan.turn_on();
+                    }
+                    match self.humidity {
+                        Some(humidity) if temp <= limits.temp_maxima => {
+                            let max = self.settings.humidity.max.unwrap_or(100.0);
+                            info!("Rackbox fan ligado [{}] (Umidade: {:.0}% > {:.0}%)",
+                                  self.settings.name, humidity, max);
+                            self.last_reason = Some(format!("humidity {:.0}% > {:.0}%",
+                                                            humidity, max));
+                        }
+                        _ => {
+                            info!("Rackbox fan ligado [{}] (Temp: {:.1}°C > {:.1}°C)",
+                                  self.settings.name, temp, limits.temp_maxima);
+                            self.last_reason = Some(format!("temp {:.1}°C > {:.1}°C",
+                                                            temp, limits.temp_maxima));
+                        }
+                    }
+                }
+                FanAction::NoChange => {
+                    info!("Zone '{}': temp {:.1}°C within range", self.settings.name, temp);
+                }
+            }
+            self.last_action = action;
+        }
+    }
+
+    fn pid_duty(&mut self, temp: f32, dt: f32) -> f32 {
+        let ceiling = self.limits().max_duty;
+        let Some(pid) = self.pid.as_mut() else {
+            return 100.0;
+        };
+        pid.set_ceiling(ceiling);
+        let output = pid.update(temp, dt);
+        debug!("Zone '{}': PID temp {:.2}°C, setpoint {:.1}°C, output {:.1}%",
+               self.settings.name, temp, pid.setpoint(), output);
+
+        match &self.settings.pwm {
+            Some(pwm) => pwm.clamp_duty(output),
+            None => output,
+        }
+    }
+
+    fn apply_duty(&mut self, duty: f32, temp: f32) {
+        let duty = if self.humid { duty.max(self.settings.humidity.duty()) } else { duty };
+        let duty = duty.min(self.limits().max_duty);
+        debug!("Zone '{}': temp {:.1}°C -> duty {:.0}%", self.settings.name, temp, duty);
+
+        let action = if duty > 0.0 { FanAction::On } else { FanAction::Off };
+        if action != self.last_action {
+            match action {
+                FanAction::On => info!("Rackbox fan ligado [{}] (Temp: {:.1}°C, duty {:.0}%)",
+                                       self.settings.name, temp, duty),
+                _ => info!("Rackbox fan desligado [{}] (Temp: {:.1}°C)",
+                           self.settings.name, temp),
+            }
+            self.last_reason = Some(format!("temp {:.1}°C -> duty {:.0}%", temp, duty));
+            self.last_action = action;
+        }
+
+        if let Ok(mut fan) = self.fan.lock() {
+            fan.set_duty(duty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fan::CurvePoint;
+    use crate::sensor::Environment;
+    use crate::sim::{FanLog, RecordingFan};
+
+    fn settings(name: &str) -> ZoneSettings {
+        ZoneSettings {
+            name: name.to_string(),
+            sensors: Vec::new(),
+            fan_gpio: 27,
+            temp_minima: 25.0,
+            temp_maxima: 30.0,
+            fan_mode: FanMode::OnOff,
+            pwm: None,
+            pid: None,
+            sensor_policy: SensorPolicy::Max,
+            failsafe: FailsafeConfig::default(),
+            cycling: CyclingConfig::default(),
+            alarm: AlarmConfig::default(),
+            humidity: HumidityConfig::default(),
+            manual: ManualConfig::default(),
+            hooks: HooksConfig::default(),
+            tach: None,
+        }
+    }
+
+    fn zone(settings: ZoneSettings, proportional: bool) -> (Zone, FanLog) {
+        let (driver, log) = RecordingFan::new(proportional);
+        let fan = FanController::with_driver("sim".to_string(), Box::new(driver));
+        (Zone::with_fan(settings, fan), log)
+    }
+
+    fn reading(name: &str, temp: f32) -> SensorReading {
+        SensorReading { name: name.to_string(), temp: Ok(temp), environment: Environment::default() }
+    }
+
+    fn failed(name: &str) -> SensorReading {
+        SensorReading {
+            name: name.to_string(),
+            temp: Err(anyhow::anyhow!("CRC check failed")),
+            environment: Environment::default(),
+        }
+    }
+
+    #[test]
+    fn hysteresis_between_thresholds() {
+        let (mut zone, log) = zone(settings("rack"), false);
+
+        for temp in [26.0, 29.0, 31.0, 28.0, 26.0, 24.0, 27.0, 29.9] {
+            zone.check(&[reading("a", temp)], 5.0);
+        }
+
+        // On above 30°C, held through the band, off below 25°C, held off again.
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn short_cycling_is_suppressed_below_the_critical_temperature() {
+        let mut limited = settings("rack");
+        limited.cycling = CyclingConfig {
+            min_on_secs: Some(60),
+            min_off_secs: Some(60),
+            max_switches_per_hour: None,
+            critical_temp: Some(35.0),
+        };
+        let (mut zone, log) = zone(limited, false);
+
+        zone.check(&[reading("a", 31.0)], 5.0);
+        // Too soon to switch off: held for 3 cycles, counted once.
+        for _ in 0..3 {
+            zone.check(&[reading("a", 24.0)], 15.0);
+        }
+        assert_eq!(log.transitions(), vec![100.0]);
+        assert_eq!(zone.status().suppressed_switches.get("min_on"), Some(&1));
+
+        zone.check(&[reading("a", 24.0)], 15.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+
+        // Minimum off time holds back a normal switch-on, not a critical one.
+        zone.check(&[reading("a", 31.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+        zone.check(&[reading("a", 36.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0, 100.0]);
+        assert_eq!(zone.status().suppressed_switches.get("min_off"), Some(&1));
+    }
+
+    #[test]
+    fn quiet_profile_is_suspended_at_its_critical_temperature() {
+        let (mut zone, log) = zone(settings("rack"), false);
+        let night = ProfileConfig {
+            name: "night".to_string(),
+            start: "23:00".to_string(),
+            end: "07:00".to_string(),
+            zones: Vec::new(),
+            temp_minima: Some(28.0),
+            temp_maxima: Some(34.0),
+            max_duty: Some(50.0),
+            critical_temp: Some(36.0),
+        };
+        zone.set_profile(Some(&night));
+
+        zone.check(&[reading("a", 32.0)], 5.0);
+        assert!(log.transitions().is_empty());
+        assert_eq!(zone.status().temp_maxima, 34.0);
+
+        // Critical: the zone's own 30°C maximum applies again.
+        zone.check(&[reading("a", 36.5)], 5.0);
+        assert!(zone.status().profile_suspended);
+        zone.check(&[reading("a", 31.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0]);
+
+        // Back under the profile maximum: quiet limits again, off below 28°C.
+        zone.check(&[reading("a", 27.0)], 5.0);
+        assert!(!zone.status().profile_suspended);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+
+        zone.set_profile(None);
+        assert_eq!(zone.status().profile, None);
+        assert_eq!(zone.status().temp_maxima, 30.0);
+    }
+
+    #[test]
+    fn alarm_level_is_reported_in_status_and_summary() {
+        let mut hot = settings("rack");
+        hot.alarm = AlarmConfig {
+            warning_temp: Some(35.0),
+            critical_temp: Some(40.0),
+            ..AlarmConfig::default()
+        };
+        let (mut zone, _log) = zone(hot, false);
+
+        zone.check(&[reading("a", 41.0)], 5.0);
+        assert_eq!(zone.status().alarm, AlarmLevel::Critical);
+        assert_eq!(zone.summary(), "rack 41.0°C fan 100% CRITICAL");
+
+        zone.check(&[reading("a", 36.0)], 5.0);
+        assert_eq!(zone.status().alarm, AlarmLevel::Warning);
+        zone.check(&[reading("a", 31.0)], 5.0);
+        assert_eq!(zone.status().alarm, AlarmLevel::Normal);
+    }
+
+    #[test]
+    fn high_humidity_runs_the_fan_until_it_drops_below_the_hysteresis() {
+        let mut basement = settings("rack");
+        basement.humidity = HumidityConfig { max: Some(70.0), ..HumidityConfig::default() };
+        let (mut zone, log) = zone(basement, false);
+        let humid = |temp: f32, humidity: f32| SensorReading {
+            name: "bme".to_string(),
+            temp: Ok(temp),
+            environment: Environment { humidity: Some(humidity), pressure: Some(1013.0) },
+        };
+
+        zone.check(&[humid(27.0, 65.0)], 5.0);
+        assert!(log.transitions().is_empty());
+        zone.check(&[humid(27.0, 75.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0]);
+        assert_eq!(zone.status().last_reason.as_deref(), Some("humidity 75% > 70%"));
+        assert_eq!(zone.summary(), "rack 27.0°C 75%RH fan 100%");
+
+        // Cool but still humid: stays on until 65%.
+        zone.check(&[humid(24.0, 67.0)], 5.0);
+        assert!(zone.status().humid);
+        assert_eq!(log.transitions(), vec![100.0]);
+        zone.check(&[humid(24.0, 64.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn read_error_forces_fan_on_until_readings_return() {
+        let (mut zone, log) = zone(settings("rack"), false);
+
+        zone.check(&[failed("a")], 5.0);
+        assert_eq!(log.last(), Some(100.0));
+
+        // Back inside the band: fail-safe state is kept.
+        zone.check(&[reading("a", 27.0)], 5.0);
+        assert_eq!(log.last(), Some(100.0));
+
+        zone.check(&[reading("a", 22.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn failures_escalate_from_degraded_to_fail_safe() {
+        let mut tolerant = settings("rack");
+        tolerant.failsafe.failures = Some(3);
+        tolerant.failsafe.lost_after_secs = Some(10);
+        let (mut zone, log) = zone(tolerant, false);
+
+        zone.check(&[failed("a")], 5.0);
+        zone.check(&[failed("a")], 5.0);
+        assert_eq!(zone.health, Health::Degraded { failures: 2 });
+        assert!(log.transitions().is_empty());
+        assert!(!zone.lost_reported);
+
+        zone.check(&[failed("a")], 5.0);
+        assert_eq!(zone.health, Health::FailSafe { failures: 3 });
+        assert_eq!(log.transitions(), vec![100.0]);
+        assert!(zone.lost_reported);
+
+        zone.check(&[reading("a", 20.0)], 5.0);
+        assert_eq!(zone.health, Health::Ok);
+        assert!(!zone.lost_reported);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn degraded_state_clears_without_touching_fan() {
+        let mut tolerant = settings("rack");
+        tolerant.failsafe.failures = Some(3);
+        let (mut zone, log) = zone(tolerant, false);
+
+        zone.check(&[reading("a", 32.0)], 5.0);
+        zone.check(&[failed("a")], 5.0);
+        zone.check(&[reading("a", 28.0)], 5.0);
+        zone.check(&[failed("a")], 5.0);
+
+        // Failures must be consecutive to escalate.
+        assert_eq!(zone.health, Health::Degraded { failures: 1 });
+        assert_eq!(log.transitions(), vec![100.0]);
+    }
+
+    #[test]
+    fn override_returns_to_auto_when_it_expires() {
+        let (mut zone, log) = zone(settings("rack"), false);
+
+        zone.set_control(Control::On, Some(Duration::from_secs(12))).unwrap();
+        zone.check(&[reading("a", 20.0)], 5.0);
+        zone.check(&[reading("a", 20.0)], 5.0);
+        assert_eq!(zone.control, Control::On);
+        assert_eq!(log.transitions(), vec![100.0]);
+
+        zone.check(&[reading("a", 20.0)], 5.0);
+        assert_eq!(zone.control, Control::Auto);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn off_override_is_cancelled_at_the_ceiling() {
+        let mut quiet = settings("rack");
+        quiet.manual.off_ceiling = Some(35.0);
+        let (mut zone, log) = zone(quiet, false);
+
+        zone.set_control(Control::Off, None).unwrap();
+        zone.check(&[reading("a", 33.0)], 5.0);
+        assert_eq!(log.transitions(), Vec::<f32>::new());
+
+        zone.check(&[reading("a", 35.5)], 5.0);
+        assert_eq!(zone.control, Control::Auto);
+        assert_eq!(log.transitions(), vec![100.0]);
+    }
+
+    #[test]
+    fn off_override_is_restored_after_a_failsafe() {
+        let (mut zone, log) = zone(settings("rack"), false);
+
+        zone.set_control(Control::Off, None).unwrap();
+        zone.check(&[failed("a")], 5.0);
+        assert_eq!(zone.control, Control::Off);
+        assert_eq!(log.transitions(), vec![100.0]);
+
+        zone.check(&[reading("a", 27.0)], 5.0);
+        assert_eq!(zone.control, Control::Off);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+        assert!(!zone.fan_on());
+    }
+
+    #[test]
+    fn override_longer_than_the_maximum_is_rejected() {
+        let mut limited = settings("rack");
+        limited.manual.max_duration_secs = Some(600);
+        let (mut zone, log) = zone(limited, false);
+
+        assert!(zone.set_control(Control::On, Some(Duration::from_secs(601))).is_err());
+        assert_eq!(zone.control, Control::Auto);
+        assert!(log.transitions().is_empty());
+    }
+
+    #[test]
+    fn max_policy_ignores_single_failed_sensor() {
+        let (mut zone, log) = zone(settings("rack"), false);
+
+        zone.check(&[failed("a"), reading("b", 20.0)], 5.0);
+        assert!(log.transitions().is_empty());
+    }
+
+    #[test]
+    fn delta_policy_fails_safe_when_one_side_is_missing() {
+        let mut delta = settings("rack");
+        delta.sensor_policy = SensorPolicy::Delta { hot: "out".into(), cold: "in".into() };
+        delta.temp_minima = 2.0;
+        delta.temp_maxima = 6.0;
+        let (mut zone, log) = zone(delta, false);
+
+        zone.check(&[reading("in", 22.0), reading("out", 30.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0]);
+        zone.check(&[reading("in", 22.0), reading("out", 23.0)], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+        zone.check(&[reading("in", 22.0), failed("out")], 5.0);
+        assert_eq!(log.transitions(), vec![100.0, 0.0, 100.0]);
+    }
+
+    #[test]
+    fn zone_only_sees_its_sensors() {
+        let mut top = settings("top");
+        top.sensors = vec!["top".into()];
+        let (mut zone, log) = zone(top, false);
+
+        zone.check(&[reading("top", 24.0), reading("psu", 45.0)], 5.0);
+        assert!(log.transitions().is_empty());
+    }
+
+    #[test]
+    fn pwm_follows_curve() {
+        let mut pwm = settings("rack");
+        pwm.fan_mode = FanMode::Pwm;
+        pwm.pwm = Some(PwmConfig {
+            channel: None,
+            frequency_hz: None,
+            min_duty: Some(30.0),
+            curve: vec![
+                CurvePoint { temp: 25.0, duty: 20.0 },
+                CurvePoint { temp: 35.0, duty: 100.0 },
+            ],
+        });
+        let (mut zone, log) = zone(pwm, true);
+
+        for temp in [20.0, 25.0, 30.0, 40.0, 24.0] {
+            zone.check(&[reading("a", temp)], 5.0);
+        }
+
+        // 25°C maps to 20% but is raised to min_duty.
+        assert_eq!(log.transitions(), vec![30.0, 60.0, 100.0, 0.0]);
+    }
+
+    #[test]
+    fn turn_off_switches_fan_off() {
+        let (mut zone, log) = zone(settings("rack"), false);
+        zone.check(&[reading("a", 35.0)], 5.0);
+        zone.turn_off();
+        assert_eq!(log.transitions(), vec![100.0, 0.0]);
+    }
+}
@@ -0,0 +1,95 @@
+//! systemd notify protocol client, shared by the Rackbox daemons. Failures
+//! are reported through the `log` crate.
+
+use log::{debug, warn};
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+/// Client for the systemd notify protocol (`Type=notify`): state lines sent
+/// as datagrams to `$NOTIFY_SOCKET`. A no-op when not started by systemd.
+pub struct Notifier {
+    target: Option<(UnixDatagram, SocketAddr)>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        match std::env::var("NOTIFY_SOCKET") {
+            Ok(path) => Self::new(&path).unwrap_or_else(|e| {
+                warn!("NOTIFY_SOCKET {}: {}", path, e);
+                Self::disabled()
+            }),
+            Err(_) => Self::disabled(),
+        }
+    }
+
+    /// Notifier for a socket path; a leading `@` is an abstract socket name.
+    pub fn new(path: &str) -> io::Result<Self> {
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Notifier { target: Some((UnixDatagram::unbound()?, addr)) })
+    }
+
+    pub fn disabled() -> Self {
+        Notifier { target: None }
+    }
+
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    /// Free-form status shown by `systemctl status`.
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status.replace('\n', " ")));
+    }
+
+    /// Keepalive for `WatchdogSec=`.
+    pub fn watchdog(&self) {
+        self.send("WATCHDOG=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    fn send(&self, state: &str) {
+        let Some((socket, addr)) = &self.target else {
+            return;
+        };
+        match socket.send_to_addr(state.as_bytes(), addr) {
+            Ok(_) => debug!("sd_notify: {}", state),
+            Err(e) => warn!("sd_notify '{}' failed: {}", state, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_states_to_socket() {
+        let path = std::env::temp_dir()
+            .join(format!("rackbox-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::new(path.to_str().unwrap()).unwrap();
+        notifier.ready();
+        notifier.status("rack 27.0°C\nfan 100%");
+        notifier.watchdog();
+        notifier.stopping();
+
+        let mut buf = [0u8; 256];
+        let received: Vec<String> = (0..4)
+            .map(|_| {
+                let n = systemd.recv(&mut buf).unwrap();
+                String::from_utf8_lossy(&buf[..n]).into_owned()
+            })
+            .collect();
+        assert_eq!(received, ["READY=1", "STATUS=rack 27.0°C fan 100%", "WATCHDOG=1", "STOPPING=1"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! Temperatures the kernel already exposes under /sys/class: hwmon chips
+//! (NVMe drives, the SoC, PMICs, ...) and thermal zones, selected by name.
+//! Shared by rackfan_daemon and rpi4_fanp17_daemon.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const SYS_CLASS: &str = "/sys/class";
+
+/// A temperature input selected by name:
+/// - `hwmon:<chip>/<label>`: a hwmon chip by its `name`, and one of its inputs
+///   by `temp*_label` or as `tempN`; the first input when `/<label>` is left out.
+/// - `thermal:<type>`: a thermal zone by its `type`, or as `thermal_zoneN`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Source {
+    Hwmon { chip: String, label: Option<String> },
+    Thermal { zone: String },
+}
+
+impl Default for Source {
+    /// The first thermal zone, the SoC's on a Raspberry Pi.
+    fn default() -> Self {
+        Source::Thermal { zone: "thermal_zone0".to_string() }
+    }
+}
+
+impl TryFrom<String> for Source {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let source = match text.split_once(':') {
+            Some(("hwmon", rest)) => match rest.split_once('/') {
+                Some((chip, label)) => Source::Hwmon {
+                    chip: chip.to_string(),
+                    label: Some(label.to_string()),
+                },
+                None => Source::Hwmon { chip: rest.to_string(), label: None },
+            },
+            Some(("thermal", zone)) => Source::Thermal { zone: zone.to_string() },
+            _ => return Err(format!(
+                "invalid source '{}', use \"hwmon:<chip>[/<label>]\" or \"thermal:<type>\"", text)),
+        };
+        match &source {
+            Source::Hwmon { chip, label }
+                if chip.is_empty() || label.as_ref().is_some_and(String::is_empty) =>
+            {
+                Err(format!("invalid source '{}': empty chip or label", text))
+            }
+            Source::Thermal { zone } if zone.is_empty() => {
+                Err(format!("invalid source '{}': empty zone type", text))
+            }
+            _ => Ok(source),
+        }
+    }
+}
+
+impl From<Source> for String {
+    fn from(source: Source) -> Self {
+        source.to_string()
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Hwmon { chip, label: Some(label) } => write!(f, "hwmon:{}/{}", chip, label),
+            Source::Hwmon { chip, label: None } => write!(f, "hwmon:{}", chip),
+            Source::Thermal { zone } => write!(f, "thermal:{}", zone),
+        }
+    }
+}
+
+impl Source {
+    fn matches(&self, input: &Input) -> bool {
+        match (self, &input.source) {
+            (Source::Hwmon { chip, label }, Source::Hwmon { chip: found, label: found_label }) => {
+                chip == found && label.as_ref()
+                    .is_none_or(|label| Some(label) == found_label.as_ref() || *label == input.alias)
+            }
+            (Source::Thermal { zone }, Source::Thermal { zone: found }) => {
+                zone == found || *zone == input.alias
+            }
+            _ => false,
+        }
+    }
+}
+
+/// One temperature input found under /sys/class.
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// Labelled name: `hwmon:<chip>/<label>` or `thermal:<type>`.
+    pub source: Source,
+    /// Positional name: `tempN` of a hwmon chip, `thermal_zoneN` of a zone.
+    pub alias: String,
+    /// File holding the temperature in millidegrees.
+    pub path: PathBuf,
+}
+
+/// Entries of `dir` named `<prefix>N<suffix>`, ordered by N.
+fn numbered(dir: &Path, prefix: &str, suffix: &str) -> Vec<(u32, PathBuf)> {
+    let mut entries: Vec<_> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let n = name.strip_prefix(prefix)?.strip_suffix(suffix)?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|text| text.trim().to_string())
+}
+
+/// Every hwmon temperature input and thermal zone under `root`
+/// (normally [`SYS_CLASS`]), hwmon first.
+pub fn inputs(root: &Path) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    for (n, dir) in numbered(&root.join("hwmon"), "hwmon", "") {
+        let chip = read_trimmed(&dir.join("name")).unwrap_or_else(|| format!("hwmon{}", n));
+        for (i, path) in numbered(&dir, "temp", "_input") {
+            let alias = format!("temp{}", i);
+            let label = read_trimmed(&dir.join(format!("temp{}_label", i)))
+                .filter(|label| !label.is_empty())
+                .unwrap_or_else(|| alias.clone());
+            inputs.push(Input {
+                source: Source::Hwmon { chip: chip.clone(), label: Some(label) },
+                alias,
+                path,
+            });
+        }
+    }
+    for (n, dir) in numbered(&root.join("thermal"), "thermal_zone", "") {
+        let alias = format!("thermal_zone{}", n);
+        let zone = read_trimmed(&dir.join("type")).unwrap_or_else(|| alias.clone());
+        inputs.push(Input { source: Source::Thermal { zone }, alias, path: dir.join("temp") });
+    }
+    inputs
+}
+
+/// File of the first input under `root` that `source` selects. hwmon
+/// numbering may change between boots, so callers look it up again after a
+/// failed read.
+pub fn find(root: &Path, source: &Source) -> Option<PathBuf> {
+    inputs(root).into_iter().find(|input| source.matches(input)).map(|input| input.path)
+}
+
+/// Why a temperature file could not be read. Each daemon words it in its
+/// own language, so the variants carry the details rather than a message.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// The file's trimmed content, which isn't a millidegree integer.
+    Invalid(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => e.fmt(f),
+            ReadError::Invalid(text) => write!(f, "invalid temperature {:?}", text),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::Io(e) => Some(e),
+            ReadError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Reads a millidegree temperature file.
+pub fn read_millidegrees(path: &Path) -> Result<f32, ReadError> {
+    let text = fs::read_to_string(path).map_err(ReadError::Io)?;
+    let millidegrees: i64 = text.trim().parse()
+        .map_err(|_| ReadError::Invalid(text.trim().to_string()))?;
+    Ok(millidegrees as f32 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn source(text: &str) -> Source {
+        Source::try_from(text.to_string()).unwrap()
+    }
+
+    fn write(path: PathBuf, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    /// A Pi 5 with an NVMe drive: SoC and PMIC hwmon chips, a drive with a
+    /// labelled and an unlabelled input, and the SoC's thermal zone.
+    fn fake_sys(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rackbox-sysfs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(root.join("hwmon/hwmon0/name"), "cpu_thermal\n");
+        write(root.join("hwmon/hwmon0/temp1_input"), "51300\n");
+        write(root.join("hwmon/hwmon1/name"), "rp1_adc\n");
+        write(root.join("hwmon/hwmon1/temp1_input"), "48000\n");
+        write(root.join("hwmon/hwmon2/name"), "nvme\n");
+        write(root.join("hwmon/hwmon2/temp1_input"), "38850\n");
+        write(root.join("hwmon/hwmon2/temp1_label"), "Composite\n");
+        write(root.join("hwmon/hwmon2/temp10_input"), "41850\n");
+        write(root.join("hwmon/hwmon2/temp2_input"), "40850\n");
+        write(root.join("hwmon/hwmon2/temp2_label"), "Sensor 1\n");
+        write(root.join("thermal/thermal_zone0/type"), "cpu-thermal\n");
+        write(root.join("thermal/thermal_zone0/temp"), "51350\n");
+        root
+    }
+
+    #[test]
+    fn sources_parse_and_print_back() {
+        assert_eq!(source("hwmon:nvme/Composite"),
+                   Source::Hwmon { chip: "nvme".into(), label: Some("Composite".into()) });
+        assert_eq!(source("hwmon:nvme"), Source::Hwmon { chip: "nvme".into(), label: None });
+        assert_eq!(source("thermal:cpu-thermal").to_string(), "thermal:cpu-thermal");
+        assert_eq!(Source::default().to_string(), "thermal:thermal_zone0");
+        for bad in ["nvme", "hwmon:", "hwmon:nvme/", "thermal:", "acpi:x"] {
+            assert!(Source::try_from(bad.to_string()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn inputs_are_listed_in_order_and_found_by_label_or_position() {
+        let root = fake_sys("list");
+
+        let names: Vec<String> = inputs(&root).iter()
+            .map(|input| format!("{} {}", input.source, input.alias))
+            .collect();
+        assert_eq!(names, [
+            "hwmon:cpu_thermal/temp1 temp1",
+            "hwmon:rp1_adc/temp1 temp1",
+            "hwmon:nvme/Composite temp1",
+            "hwmon:nvme/Sensor 1 temp2",
+            "hwmon:nvme/temp10 temp10",
+            "thermal:cpu-thermal thermal_zone0",
+        ]);
+
+        let path = |text: &str| find(&root, &source(text));
+        assert_eq!(path("hwmon:nvme"), Some(root.join("hwmon/hwmon2/temp1_input")));
+        assert_eq!(path("hwmon:nvme/Sensor 1"), Some(root.join("hwmon/hwmon2/temp2_input")));
+        assert_eq!(path("hwmon:nvme/temp2"), Some(root.join("hwmon/hwmon2/temp2_input")));
+        assert_eq!(path("thermal:cpu-thermal"), Some(root.join("thermal/thermal_zone0/temp")));
+        assert_eq!(path("thermal:thermal_zone0"), Some(root.join("thermal/thermal_zone0/temp")));
+        assert_eq!(path("hwmon:nvme/Sensor 2"), None);
+        assert_eq!(path("thermal:cpu_thermal"), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn source_follows_its_chip_when_hwmon_is_renumbered() {
+        let root = fake_sys("renumber");
+        let ssd = source("hwmon:nvme/Composite");
+        let read = |root: &Path| read_millidegrees(&find(root, &ssd).unwrap());
+        assert_eq!(read(&root).unwrap(), 38.85);
+
+        // The drive comes back as hwmon3 (e.g. after a controller reset).
+        fs::rename(root.join("hwmon/hwmon2"), root.join("hwmon/hwmon3")).unwrap();
+        write(root.join("hwmon/hwmon3/temp1_input"), "-1500\n");
+        assert_eq!(read(&root).unwrap(), -1.5);
+
+        write(root.join("hwmon/hwmon3/temp1_input"), "garbage\n");
+        assert!(matches!(read(&root), Err(ReadError::Invalid(text)) if text == "garbage"));
+
+        fs::remove_dir_all(root.join("hwmon/hwmon3")).unwrap();
+        assert_eq!(find(&root, &ssd), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+}